@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{rc::Rc, sync::Arc};
 
 use chrono::Utc;
 use winit::{
@@ -13,33 +13,158 @@ use winit::{
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-use crate::{canvas::Canvas, game::Game, renderer::Renderer, time::Clock};
+use crate::{
+    Options,
+    canvas::Canvas,
+    game::{Game, GameRequest, GameState, LAST_REPLAY_KEY},
+    pieces::PieceSet,
+    playback::{Playback, PlaybackRequest},
+    renderer::Renderer,
+    replay::Replay,
+    rules::Rules,
+    snapshot, storage,
+    time::Clock,
+};
 
 pub struct App {
     #[cfg(target_arch = "wasm32")]
     proxy: Option<winit::event_loop::EventLoopProxy<Canvas>>,
     pub game: Option<Game>,
+    /// A replay being watched, which is shown and gets the keyboard instead of `game`
+    pub playback: Option<Playback>,
     pub renderer: Option<Renderer>,
     pub canvas: Option<Canvas>,
     pub is_surface_configured: bool,
     pub clock: Clock,
+    options: Options,
+    /// The built-in piece sets followed by the ones from the options
+    piece_sets: Rc<[PieceSet]>,
 }
 
 impl App {
-    pub fn new(#[allow(unused)] event_loop: &EventLoop<Canvas>) -> Self {
+    pub fn new(
+        #[allow(unused)] event_loop: &EventLoop<Canvas>,
+        options: Options,
+        piece_sets: Rc<[PieceSet]>,
+    ) -> Self {
         #[cfg(target_arch = "wasm32")]
         let proxy = Some(event_loop.create_proxy());
         Self {
             game: None,
+            playback: None,
             renderer: None,
             canvas: None,
             is_surface_configured: false,
             clock: Clock::now(),
+            options,
+            piece_sets,
             #[cfg(target_arch = "wasm32")]
             proxy,
         }
     }
 
+    /// Offers to continue the saved game if there is one, or else starts on the title screen
+    fn create_game(&self) -> Game {
+        if let Some(text) = storage::load(snapshot::STORAGE_KEY) {
+            match Game::from_snapshot(&text, self.piece_sets.clone()) {
+                Ok(game) => return game,
+                Err(err) => log::warn!("Failed to load the saved game: {:#}", err),
+            }
+        }
+        self.create_new_game()
+    }
+
+    /// Starts watching the replay from the options, if there is one
+    fn start_options_playback(&mut self) {
+        let Some(bytes) = &self.options.replay else {
+            return;
+        };
+        match Replay::decode(bytes) {
+            Ok(replay) => self.watch_replay(replay),
+            Err(err) => log::warn!("Failed to read the replay: {:#}", err),
+        }
+    }
+
+    fn watch_replay(&mut self, replay: Replay) {
+        match Playback::new(replay, self.piece_sets.clone()) {
+            Ok(playback) => self.playback = Some(playback),
+            Err(err) => log::warn!("Failed to play back the replay: {:#}", err),
+        }
+    }
+
+    fn create_new_game(&self) -> Game {
+        // Custom piece sets are chosen over the built-in ones
+        let builtin_count = self.piece_sets.len() - self.options.piece_sets.len();
+        let rules = Rules {
+            board_width: self.options.board_width,
+            board_height: self.options.board_height,
+            time_limit_secs: self.options.time_limit_secs,
+            fade_delay_ms: self.options.fade_delay_ms,
+            lock_delay_ms: self.options.lock_delay_ms,
+            max_lock_resets: self.options.max_lock_resets,
+            das_ms: self.options.das_ms,
+            arr_ms: self.options.arr_ms,
+            soft_drop: self.options.soft_drop,
+            piece_set: if self.options.piece_sets.is_empty() {
+                0
+            } else {
+                builtin_count
+            },
+            ..Rules::default()
+        };
+        let seed = self.options.seed.unwrap_or_else(rand::random);
+        Game::new(rules, seed, self.piece_sets.clone())
+    }
+
+    /// Saves the game so that it can be continued later, or removes the old save if there is no
+    /// game in progress
+    fn save_game(&self) {
+        let Some(game) = &self.game else {
+            return;
+        };
+        let result = if game.in_progress() {
+            storage::save(snapshot::STORAGE_KEY, &game.to_snapshot())
+        } else {
+            storage::remove(snapshot::STORAGE_KEY)
+        };
+        if let Err(err) = result {
+            log::warn!("Failed to save the game: {:#}", err);
+        }
+    }
+
+    fn handle_game_request(&mut self, request: GameRequest, event_loop: &ActiveEventLoop) {
+        let Some(game) = &mut self.game else {
+            return;
+        };
+
+        match request {
+            GameRequest::Restart { new_seed } => {
+                let seed = if new_seed { rand::random() } else { game.seed };
+                *game = Game::new(game.rules, seed, game.piece_sets.clone());
+                game.start();
+            }
+            GameRequest::ReturnToTitle => {
+                let seed = self.options.seed.unwrap_or_else(rand::random);
+                *game = Game::new(game.rules, seed, game.piece_sets.clone());
+            }
+            GameRequest::WatchReplay => {
+                let replay = if matches!(game.state, GameState::GameOver | GameState::Won) {
+                    game.replay.clone()
+                } else {
+                    storage::load_bytes(LAST_REPLAY_KEY).and_then(|bytes| {
+                        Replay::decode(&bytes)
+                            .inspect_err(|err| log::warn!("Failed to read the replay: {:#}", err))
+                            .ok()
+                    })
+                };
+                if let Some(replay) = replay {
+                    self.watch_replay(replay);
+                }
+            }
+            GameRequest::Quit => event_loop.exit(),
+        }
+    }
+
     fn resize(&mut self, width: u32, height: u32) {
         let Some(canvas) = &mut self.canvas else {
             return;
@@ -59,7 +184,7 @@ impl ApplicationHandler<Canvas> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         #[allow(unused_mut)]
         let mut window_attributes =
-            Window::default_attributes().with_inner_size(dpi::LogicalSize::new(320, 640));
+            Window::default_attributes().with_inner_size(dpi::LogicalSize::new(640, 640));
 
         #[cfg(target_arch = "wasm32")]
         {
@@ -80,7 +205,8 @@ impl ApplicationHandler<Canvas> for App {
         #[cfg(not(target_arch = "wasm32"))]
         {
             let canvas = pollster::block_on(Canvas::new(window)).unwrap();
-            self.game = Some(Game::default());
+            self.game = Some(self.create_game());
+            self.start_options_playback();
             self.renderer =
                 Some(Renderer::new(&canvas.device, &canvas.queue, &canvas.config).unwrap());
             self.canvas = Some(canvas);
@@ -115,7 +241,8 @@ impl ApplicationHandler<Canvas> for App {
                 canvas.resize(width, height);
             }
         }
-        self.game = Some(Game::default());
+        self.game = Some(self.create_game());
+        self.start_options_playback();
         self.renderer = Some(Renderer::new(&canvas.device, &canvas.queue, &canvas.config).unwrap());
         self.canvas = Some(canvas);
     }
@@ -135,6 +262,7 @@ impl ApplicationHandler<Canvas> for App {
         let Some(renderer) = &mut self.renderer else {
             return;
         };
+        let state_before = game.state;
 
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
@@ -144,12 +272,18 @@ impl ApplicationHandler<Canvas> for App {
             }
             WindowEvent::RedrawRequested => {
                 let time_passed = self.clock.update(Utc::now());
-                game.update(time_passed);
+                match &mut self.playback {
+                    Some(playback) => playback.update(time_passed),
+                    None => game.update(time_passed),
+                }
                 canvas.window.request_redraw();
                 if !self.is_surface_configured {
                     return;
                 }
-                match renderer.render(game, canvas) {
+                game.load_records();
+                let playback = self.playback.as_ref();
+                let shown = playback.map_or(&*game, |playback| &playback.game);
+                match renderer.render(shown, playback, canvas) {
                     Ok(_) => {}
                     Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
                         let size = canvas.window.inner_size();
@@ -160,7 +294,8 @@ impl ApplicationHandler<Canvas> for App {
                     }
                 }
             }
-            WindowEvent::Focused(focused) => {
+            // The game behind a replay being watched is never running, so focus only matters without one
+            WindowEvent::Focused(focused) if self.playback.is_none() => {
                 game.on_focus_changed(focused);
             }
             WindowEvent::KeyboardInput {
@@ -168,11 +303,34 @@ impl ApplicationHandler<Canvas> for App {
                     KeyEvent {
                         physical_key: PhysicalKey::Code(code),
                         state: key_state,
+                        // held keys are repeated by the game itself (see `AutoShift`)
+                        repeat: false,
                         ..
                     },
                 ..
-            } => game.handle_key(code, key_state.is_pressed()),
+            } => {
+                if let Some(playback) = &mut self.playback {
+                    match playback.handle_key(code, key_state.is_pressed()) {
+                        Some(PlaybackRequest::Exit) => self.playback = None,
+                        None => {}
+                    }
+                } else if let Some(request) = game.handle_key(code, key_state.is_pressed()) {
+                    self.handle_game_request(request, event_loop);
+                }
+            }
             _ => {}
         }
+
+        // The game is saved when it pauses, and the save is removed when the game ends
+        if let Some(game) = &self.game
+            && game.state != state_before
+            && game.state != GameState::Running
+        {
+            self.save_game();
+        }
+    }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        self.save_game();
     }
 }
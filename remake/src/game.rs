@@ -1,69 +1,343 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     ops::{Add, Index},
+    rc::Rc,
 };
 
+use anyhow::{Context, ensure};
 use chrono::TimeDelta;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use winit::keyboard::KeyCode;
 
-use crate::time::Timer;
+use crate::{
+    input::{Action, AutoShift},
+    menu::Menu,
+    mode::{GameMode, Record},
+    pieces::PieceSet,
+    puzzle::{Puzzle, PuzzleGoal},
+    randomizer::Randomizer,
+    replay::{Replay, ReplayEvent},
+    rules::{
+        BOARD_HEIGHTS, BOARD_WIDTHS, CHEESE_MESSINESS, CHEESE_ROWS, MAX_PREVIEW_COUNT, Rules,
+        SoftDrop, Visibility,
+    },
+    scoring::{LineClear, ScoringTable, TSpin},
+    snapshot::{SnapshotReader, SnapshotWriter, read_rules, write_rules},
+    srs::KickTable,
+    storage,
+    time::{Timer, format_duration},
+};
+
+/// The number of rows above the visible board
+pub const HIDDEN_ROWS: u8 = 20;
+
+/// How long it takes for a tile to disappear once it starts fading
+const FADE_OUT_MS: i64 = 500;
+
+/// The storage key of the replay of the last finished game
+pub const LAST_REPLAY_KEY: &str = "replays/last";
 
 pub struct Game {
+    pub rules: Rules,
+    /// Games with the same seed and rules get the same pieces
+    pub seed: u64,
+    rng: ChaCha8Rng,
     pub board: Board,
     pub state: GameState,
+    /// The piece sets that can be chosen in the rules
+    pub piece_sets: Rc<[PieceSet]>,
     pub shapes: HashMap<char, Shape>,
-    pub moving_piece_timer: Timer,
+    /// How far the moving piece has fallen towards the next row
+    fall_progress: f64,
     pub moving_piece: Option<Piece>,
-    pub next_shape: Option<char>,
+    auto_shift: AutoShift,
+    soft_drop_held: bool,
+    /// Counts down while the moving piece rests on something, and locks it when it runs out
+    lock_timer: Timer,
+    /// How many times the lock delay has been restarted for the moving piece
+    lock_resets: u32,
+    /// The wall kick that was used, if the last thing that moved the piece was a rotation
+    last_kick: Option<Kick>,
+    /// The lowest the moving piece has been, which is where the lock resets were counted from
+    lowest_row: i8,
+    /// The upcoming pieces, with the next one first
+    pub next_shapes: VecDeque<char>,
+    pub held_shape: Option<char>,
+    /// Only one hold is allowed per piece, until it locks
+    hold_used: bool,
     pub progress: GameProgress,
+    /// Whether the game has been won and the player chose to keep playing
+    pub post_game: bool,
+    /// The column of the hole in the next garbage row
+    garbage_hole: u8,
+    /// Counts down to the next garbage row, in modes where it rises
+    garbage_timer: Timer,
+    /// The best time from before this game, in modes that are about time
+    pub best_time: Option<TimeDelta>,
+    /// The best score from before this game, in modes that are about score
+    pub best_score: Option<u32>,
+    /// Whether `best_time` and `best_score` have been read from storage
+    records_loaded: bool,
+    /// Whether this game beat the best result
+    pub new_best: bool,
+    /// Shown when the game is over or has been won, or when a saved game can be continued
+    pub end_menu: Option<Menu<EndMenuOption>>,
+    /// Shown for a while after a special clear, like a T-spin
+    pub clear_message: Option<String>,
+    clear_message_timer: Timer,
+    /// Games with a fixed queue of pieces have no randomizer, and end when the queue runs out
+    randomizer: Option<Box<dyn Randomizer>>,
+    /// The puzzle being played, in puzzle mode
+    pub puzzle: Option<Puzzle>,
+    /// Everything that has happened in the game, unless it was not started from a seed
+    pub replay: Option<Replay>,
+    /// Whether the game is played back from a replay, in which case no results are saved
+    pub playback: bool,
 }
 
 impl Default for Game {
     fn default() -> Self {
-        let shapes: HashMap<char, Shape> = [
-            ('O', [(0, -1), (0, 0), (1, 0), (1, -1)]),
-            ('I', [(0, -1), (0, 0), (0, 1), (0, 2)]),
-            ('J', [(1, -1), (1, 0), (1, 1), (0, 1)]),
-            ('L', [(0, -1), (0, 0), (0, 1), (1, 1)]),
-            ('Z', [(1, -1), (1, 0), (0, 0), (0, 1)]),
-            ('S', [(0, -1), (0, 0), (1, 0), (1, 1)]),
-            ('T', [(0, -1), (0, 0), (0, 1), (1, 0)]),
-        ]
-        .map(|(l, offsets)| (l, Shape::new(offsets.map(|(dx, dy)| Pos::new(dx, dy)))))
-        .into_iter()
-        .collect();
+        Self::new(Rules::default(), rand::random(), PieceSet::builtin().into())
+    }
+}
+
+impl Game {
+    pub fn new(rules: Rules, seed: u64, piece_sets: Rc<[PieceSet]>) -> Self {
+        if rules.mode == GameMode::Puzzle {
+            // The puzzles are made for the standard pieces, and can't be solved with other ones
+            let rules = Rules {
+                piece_set: 0,
+                ..rules
+            };
+            let puzzles = Puzzle::builtin();
+            let puzzle = puzzles[rules.puzzle % puzzles.len()].clone();
+            let board = puzzle.board(rules.board_height);
+            let mut game = Game::from_board(rules, seed, piece_sets, board, puzzle.queue.clone());
+            game.puzzle = Some(puzzle);
+            game.replay = Some(Replay::new(seed, rules, game.piece_set().name.clone()));
+            return game;
+        }
+
+        let mut game = Game::empty(rules, seed, piece_sets);
+        if rules.mode.has_garbage() {
+            game.garbage_hole = game.rng.random_range(0..game.board.width);
+        }
+        if rules.mode.starts_with_garbage() {
+            let rows = rules.cheese_rows.min(rules.board_height - 1);
+            for _ in 0..rows {
+                game.add_garbage_row();
+            }
+        }
+        game.fill_next_shapes();
+        game.replay = Some(Replay::new(seed, rules, game.piece_set().name.clone()));
+        game
+    }
+
+    /// A game that starts on the given board and only gets the pieces in the queue. Tiles with a
+    /// letter that is not in the piece set become garbage, and such pieces are left out of the
+    /// queue.
+    pub fn from_board(
+        rules: Rules,
+        seed: u64,
+        piece_sets: Rc<[PieceSet]>,
+        mut board: Board,
+        queue: Vec<char>,
+    ) -> Self {
+        let mut game = Game::empty(rules, seed, piece_sets);
+        for y in 0..board.height as i8 {
+            for x in 0..board.width as i8 {
+                let pos = Pos::new(x, y);
+                if let Some(tile) = board.get_tile(pos)
+                    && tile != GARBAGE_TILE
+                    && !game.shapes.contains_key(&tile)
+                {
+                    board.set_tile(pos, GARBAGE_TILE, board.locked_at(pos));
+                }
+            }
+        }
+        game.board = board;
+        game.randomizer = None;
+        game.next_shapes = queue
+            .into_iter()
+            .filter(|letter| game.shapes.contains_key(letter))
+            .collect();
+        game
+    }
+
+    /// A game on an empty board, before any pieces are put in the queue
+    fn empty(rules: Rules, seed: u64, piece_sets: Rc<[PieceSet]>) -> Self {
+        let piece_set = &piece_sets[rules.piece_set];
+        let shapes: HashMap<char, Shape> = piece_set
+            .pieces
+            .iter()
+            .map(|p| (p.letter, p.shape.clone()))
+            .collect();
+        let letters = piece_set.letters();
 
         Self {
+            rules,
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            randomizer: Some(rules.randomizer.create(&letters)),
+            puzzle: None,
+            replay: None,
+            playback: false,
+            piece_sets,
             shapes,
-            board: Board::new(10, 20),
+            board: Board::new(rules.board_width, rules.board_height, HIDDEN_ROWS),
             state: GameState::NotStarted,
             moving_piece: None,
-            next_shape: None,
+            auto_shift: AutoShift::new(),
+            soft_drop_held: false,
+            lock_timer: Timer::new(),
+            lock_resets: 0,
+            last_kick: None,
+            lowest_row: 0,
+            post_game: false,
+            garbage_hole: 0,
+            garbage_timer: Timer::new(),
+            best_time: None,
+            best_score: None,
+            records_loaded: false,
+            new_best: false,
+            end_menu: None,
+            clear_message: None,
+            clear_message_timer: Timer::new(),
+            next_shapes: VecDeque::new(),
+            held_shape: None,
+            hold_used: false,
 
-            progress: GameProgress::new(60, 10),
-            moving_piece_timer: Timer::new(),
+            progress: GameProgress::new(60, rules.mode.rows_per_level(), rules.scoring),
+            fall_progress: 0.0,
         }
     }
-}
 
-impl Game {
-    /// 800 ms (level 0) to 0 ms (max level), reducing faster in the beginning
-    fn time_between_moves(&self) -> TimeDelta {
-        use std::f32::consts::PI;
+    /// Pushes up a garbage row with one hole from the bottom. The messiness is the chance that the
+    /// hole moves to another column from one row to the next.
+    ///
+    /// The moving piece is pushed up as well, and the game is over if anything is pushed out of the
+    /// top of the board.
+    fn add_garbage_row(&mut self) {
+        let pushed_out = self
+            .board
+            .insert_garbage_row(self.garbage_hole, self.progress.time);
 
-        let progress = self.progress.level as f32 / self.progress.levels_to_win as f32;
-        let speed_up = (progress * PI / 2.0).sin();
-        TimeDelta::milliseconds(((1.0 - speed_up) * 800.0) as i64)
+        let width = self.board.width;
+        if self.rng.random_range(0..100) < self.rules.cheese_messiness {
+            // Any other column, so that the hole always moves
+            self.garbage_hole = (self.garbage_hole + self.rng.random_range(1..width)) % width;
+        }
+
+        if let Some(piece) = self.moving_piece
+            && self.piece_collides(piece)
+        {
+            let pushed = piece.moved(Pos::new(0, -1));
+            self.moving_piece = Some(pushed);
+            if self.piece_collides(pushed) {
+                self.end(GameState::GameOver);
+                return;
+            }
+        }
+        if pushed_out {
+            self.end(GameState::GameOver);
+        }
+    }
+
+    pub fn piece_set(&self) -> &PieceSet {
+        &self.piece_sets[self.rules.piece_set]
+    }
+
+    /// Starts over with new rules. Only meant to be used before the game has started.
+    fn change_rules(&mut self, update_fn: impl FnOnce(&mut Rules)) {
+        let mut rules = self.rules;
+        update_fn(&mut rules);
+        *self = Game::new(rules, self.seed, self.piece_sets.clone());
+    }
+
+    fn rows_per_second(&self) -> f64 {
+        let progress = &self.progress;
+        self.rules
+            .gravity
+            .rows_per_second(progress.level, progress.levels_to_win)
     }
 
     pub fn on_focus_changed(&mut self, focused: bool) {
-        if !focused && self.state == GameState::Running {
-            self.state = GameState::Paused;
+        if !focused {
+            // The key releases will not be seen while the window is unfocused
+            self.release_all_actions();
+
+            if self.state == GameState::Running {
+                self.state = GameState::Paused;
+            }
         }
     }
 
-    pub fn handle_key(&mut self, code: KeyCode, is_pressed: bool) {
+    /// Unpauses a game from `from_snapshot`
+    fn continue_saved(&mut self) {
+        self.end_menu = None;
+        self.state = GameState::Running;
+    }
+
+    fn release_all_actions(&mut self) {
+        self.record(ReplayEvent::ReleaseAll);
+        self.auto_shift.release_all();
+        self.soft_drop_held = false;
+    }
+
+    fn keep_playing(&mut self) {
+        self.record(ReplayEvent::KeepPlaying);
+        self.end_menu = None;
+        self.post_game = true;
+        self.state = GameState::Running;
+    }
+
+    fn record(&mut self, event: ReplayEvent) {
+        if let Some(replay) = &mut self.replay {
+            replay.record(event);
+        }
+    }
+
+    /// Does the same thing as when the event was recorded
+    pub fn apply_replay_event(&mut self, event: ReplayEvent) {
+        match event {
+            ReplayEvent::Update(time_passed) => self.update(time_passed),
+            ReplayEvent::Press(action) => self.handle_action(action, true),
+            ReplayEvent::Release(action) => self.handle_action(action, false),
+            ReplayEvent::ReleaseAll => self.release_all_actions(),
+            ReplayEvent::KeepPlaying => self.keep_playing(),
+        }
+    }
+
+    /// Returns a request if the player chose to do something that the app has to take care of
+    pub fn handle_key(&mut self, code: KeyCode, is_pressed: bool) -> Option<GameRequest> {
+        let can_restart = matches!(
+            self.state,
+            GameState::Paused | GameState::GameOver | GameState::Won
+        );
+        if code == KeyCode::KeyR && is_pressed && can_restart {
+            return Some(GameRequest::Restart { new_seed: true });
+        }
+
+        if is_pressed && let Some(menu) = &mut self.end_menu {
+            match menu.handle_key(code)? {
+                EndMenuOption::KeepPlaying => self.keep_playing(),
+                EndMenuOption::Resume => self.continue_saved(),
+                EndMenuOption::WatchReplay => return Some(GameRequest::WatchReplay),
+                EndMenuOption::NextPuzzle => {
+                    let count = Puzzle::builtin().len();
+                    self.change_rules(|r| r.puzzle = (r.puzzle + 1) % count);
+                }
+                EndMenuOption::Retry => return Some(GameRequest::Restart { new_seed: false }),
+                EndMenuOption::RetryWithNewSeed => {
+                    return Some(GameRequest::Restart { new_seed: true });
+                }
+                EndMenuOption::Title => return Some(GameRequest::ReturnToTitle),
+                EndMenuOption::Quit => return Some(GameRequest::Quit),
+            }
+            return None;
+        }
+
         match (code, is_pressed) {
             (KeyCode::Escape, true) => {
                 self.state = match self.state {
@@ -79,40 +353,521 @@ impl Game {
                     state => state,
                 };
             }
-            (KeyCode::Space, true) => {
-                if self.state == GameState::NotStarted {
-                    self.state = GameState::Running;
+            (KeyCode::Space, true) => self.start(),
+            (KeyCode::Tab, true) if self.state == GameState::NotStarted => {
+                self.change_rules(|r| r.scoring = r.scoring.next());
+            }
+            (KeyCode::KeyR, true) if self.state == GameState::NotStarted => {
+                self.change_rules(|r| r.rotation_180 = !r.rotation_180);
+            }
+            (KeyCode::KeyB, true) if self.state == GameState::NotStarted => {
+                self.change_rules(|r| r.randomizer = r.randomizer.next());
+            }
+            (KeyCode::KeyN, true) if self.state == GameState::NotStarted => {
+                self.change_rules(|r| r.preview_count = r.preview_count % MAX_PREVIEW_COUNT + 1);
+            }
+            (KeyCode::KeyT, true) if self.state == GameState::NotStarted => {
+                self.change_rules(|r| r.top_out = r.top_out.next());
+            }
+            (KeyCode::KeyM, true) if self.state == GameState::NotStarted => {
+                self.change_rules(|r| r.mode = r.mode.next());
+            }
+            (KeyCode::KeyH, true) if self.state == GameState::NotStarted => {
+                self.change_rules(|r| r.cheese_rows = next_option(r.cheese_rows, &CHEESE_ROWS));
+            }
+            (KeyCode::KeyJ, true) if self.state == GameState::NotStarted => {
+                self.change_rules(|r| {
+                    r.cheese_messiness = next_option(r.cheese_messiness, &CHEESE_MESSINESS)
+                });
+            }
+            (KeyCode::KeyV, true) if self.state == GameState::NotStarted => {
+                self.change_rules(|r| r.visibility = r.visibility.next());
+            }
+            (KeyCode::KeyG, true) if self.state == GameState::NotStarted => {
+                self.change_rules(|r| r.gravity = r.gravity.next());
+            }
+            (KeyCode::KeyW, true) if self.state == GameState::NotStarted => {
+                return Some(GameRequest::WatchReplay);
+            }
+            (KeyCode::KeyL, true) if self.state == GameState::NotStarted => {
+                let count = Puzzle::builtin().len();
+                self.change_rules(|r| r.puzzle = (r.puzzle + 1) % count);
+            }
+            (KeyCode::KeyS, true)
+                if self.state == GameState::NotStarted && self.puzzle.is_none() =>
+            {
+                let count = self.piece_sets.len();
+                self.change_rules(|r| r.piece_set = (r.piece_set + 1) % count);
+            }
+            _ => {
+                if let Some(action) = Action::from_key(code) {
+                    self.handle_action(action, is_pressed);
                 }
             }
-            (KeyCode::ArrowUp, true) => {
-                if let Some(piece) = self.try_update_moving_piece(|p| p.rotated_cw()) {
-                    self.moving_piece = Some(piece);
+        }
+        None
+    }
+
+    pub fn start(&mut self) {
+        if self.state == GameState::NotStarted {
+            self.state = GameState::Running;
+        }
+    }
+
+    fn end(&mut self, state: GameState) {
+        self.state = state;
+        if self.playback {
+            return;
+        }
+
+        let mut options = vec![
+            ("Retry", EndMenuOption::Retry),
+            ("Retry with new seed", EndMenuOption::RetryWithNewSeed),
+            ("Watch replay", EndMenuOption::WatchReplay),
+            ("Title", EndMenuOption::Title),
+            ("Quit", EndMenuOption::Quit),
+        ];
+        if state == GameState::Won && self.rules.mode.has_post_game() {
+            options.insert(0, ("Keep playing", EndMenuOption::KeepPlaying));
+        }
+        if state == GameState::Won && self.puzzle.is_some() {
+            options.insert(0, ("Next puzzle", EndMenuOption::NextPuzzle));
+        }
+        self.end_menu = Some(Menu::new(options));
+
+        if state == GameState::Won || self.rules.mode.ends_at_top_out() {
+            self.save_record();
+        }
+        if let Some(replay) = &mut self.replay {
+            replay.finish(&self.board);
+        }
+        self.save_replay();
+    }
+
+    /// Saves the replay of the last game, and of the best game in modes with a record
+    fn save_replay(&self) {
+        let Some(replay) = &self.replay else {
+            return;
+        };
+        let bytes = replay.encode();
+        let mut keys = vec![LAST_REPLAY_KEY.to_string()];
+        if self.new_best
+            && let Some(record) = self.rules.mode.record()
+        {
+            let key = record_key(&self.rules, self.piece_set(), record);
+            keys.push(format!("replays/{key}"));
+        }
+        for key in keys {
+            if let Err(err) = storage::save_bytes(&key, &bytes) {
+                log::warn!("Failed to save the replay: {:#}", err);
+            }
+        }
+    }
+
+    /// Reads the best results for these rules the first time it is called, so that games that are
+    /// never shown, like the ones made while changing settings or seeking in a replay, don't have to
+    pub fn load_records(&mut self) {
+        if self.records_loaded || self.playback {
+            return;
+        }
+        self.records_loaded = true;
+
+        let (rules, piece_set) = (&self.rules, self.piece_set());
+        let best_time = load_record(rules, piece_set, Record::Time)
+            .or_else(|| load_record(rules, piece_set, Record::TimeSurvived))
+            .map(TimeDelta::milliseconds);
+        let best_score = load_record(rules, piece_set, Record::Score).map(|score| score as u32);
+        self.best_time = best_time;
+        self.best_score = best_score;
+    }
+
+    /// Saves the result of a won game if it is the best one so far
+    fn save_record(&mut self) {
+        let Some(record) = self.rules.mode.record() else {
+            return;
+        };
+        self.load_records();
+        let value = match record {
+            Record::Time => {
+                let time = self.progress.time;
+                if self.best_time.is_some_and(|best| best <= time) {
+                    return;
                 }
+                time.num_milliseconds()
             }
-            (KeyCode::ArrowLeft, true) => {
-                if let Some(piece) = self.try_update_moving_piece(|p| p.moved(Pos::new(-1, 0))) {
-                    self.moving_piece = Some(piece);
+            Record::Score => {
+                let score = self.progress.score;
+                if self.best_score.is_some_and(|best| best >= score) {
+                    return;
                 }
+                score as i64
             }
-            (KeyCode::ArrowDown, true) => {
-                if let Some(piece) = self.try_update_moving_piece(|p| p.moved(Pos::new(0, 1))) {
-                    self.moving_piece = Some(piece);
+            Record::TimeSurvived => {
+                let time = self.progress.time;
+                if self.best_time.is_some_and(|best| best >= time) {
+                    return;
                 }
+                time.num_milliseconds()
+            }
+        };
+        self.new_best = true;
+
+        let key = record_key(&self.rules, self.piece_set(), record);
+        if let Err(err) = storage::save(&key, &value.to_string()) {
+            log::warn!("Failed to save best {}: {:#}", record.name(), err);
+        }
+    }
+
+    /// How visible a tile on the board is, from 0 to 1. Everything is shown when the game is over.
+    pub fn tile_opacity(&self, pos: Pos) -> f32 {
+        if matches!(self.state, GameState::GameOver | GameState::Won) {
+            return 1.0;
+        }
+        match self.rules.visibility {
+            Visibility::Visible => 1.0,
+            Visibility::Fading => {
+                let age = self.progress.time - self.board.locked_at(pos);
+                let fading_ms = age.num_milliseconds() - self.rules.fade_delay_ms as i64;
+                1.0 - (fading_ms as f32 / FADE_OUT_MS as f32).clamp(0.0, 1.0)
+            }
+            Visibility::Invisible => 0.0,
+        }
+    }
+
+    /// The time left in modes with a time limit
+    pub fn time_left(&self) -> Option<TimeDelta> {
+        if !self.rules.mode.has_time_limit() {
+            return None;
+        }
+        let limit = TimeDelta::seconds(self.rules.time_limit_secs as i64);
+        Some((limit - self.progress.time).max(TimeDelta::zero()))
+    }
+
+    /// How the game went and what the board looks like, as text
+    pub fn summary(&self) -> String {
+        let progress = &self.progress;
+        let mut text = format!(
+            "{:?} after {}\nScore: {}\nLines: {}\nPieces: {}\n",
+            self.state,
+            format_duration(progress.time),
+            progress.score,
+            progress.lines,
+            progress.pieces,
+        );
+        for y in 0..self.board.height as i8 {
+            for x in 0..self.board.width as i8 {
+                text.push(self.board.get_tile(Pos::new(x, y)).unwrap_or('.'));
             }
-            (KeyCode::ArrowRight, true) => {
-                if let Some(piece) = self.try_update_moving_piece(|p| p.moved(Pos::new(1, 0))) {
-                    self.moving_piece = Some(piece);
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Whether the game has started and is not over yet, so that it can be saved and continued
+    pub fn in_progress(&self) -> bool {
+        matches!(self.state, GameState::Running | GameState::Paused)
+    }
+
+    /// Everything needed to continue the game later, in the format described in `snapshot.rs`
+    pub fn to_snapshot(&self) -> String {
+        let mut w = SnapshotWriter::new();
+        w.field("seed", self.seed);
+        write_rules(&mut w, &self.rules);
+        w.field("piece-set-name", &self.piece_set().name);
+        w.field("rng", self.rng.get_word_pos());
+        if let Some(randomizer) = &self.randomizer {
+            w.field("randomizer-state", randomizer.state());
+        }
+
+        let board = &self.board;
+        w.field("board", format!("{} {}", board.width, board.height));
+        for y in -(board.hidden_rows as i8)..board.height as i8 {
+            for x in 0..board.width as i8 {
+                let pos = Pos::new(x, y);
+                if let Some(tile) = board.get_tile(pos) {
+                    let locked_at = board.locked_at(pos).num_nanoseconds().unwrap_or(0);
+                    w.field("cell", format!("{x} {y} {tile} {locked_at}"));
                 }
             }
-            (KeyCode::KeyD, true) => {
-                while let Some(piece) = self.try_update_moving_piece(|p| p.moved(Pos::new(0, 1))) {
-                    self.moving_piece = Some(piece);
+        }
+
+        if let Some(piece) = self.moving_piece {
+            let Pos { x, y } = piece.origin;
+            w.field(
+                "piece",
+                format!("{} {} {x} {y}", piece.letter, piece.rotation),
+            );
+        }
+        w.field("queue", self.next_shapes.iter().collect::<String>());
+        if let Some(held) = self.held_shape {
+            w.field("held", held);
+        }
+        w.field("hold-used", self.hold_used);
+        self.auto_shift.write_snapshot(&mut w);
+        w.field("soft-drop-held", self.soft_drop_held);
+        w.field("fall-progress", self.fall_progress);
+        w.duration("lock-timer", self.lock_timer.elapsed());
+        w.field("lock-resets", self.lock_resets);
+        if let Some(kick) = self.last_kick {
+            let Pos { x, y } = kick.offset;
+            w.field("last-kick", format!("{x} {y} {}", kick.half_turn));
+        }
+        w.field("lowest-row", self.lowest_row);
+        w.field("post-game", self.post_game);
+        w.field("garbage-hole", self.garbage_hole);
+        w.duration("garbage-timer", self.garbage_timer.elapsed());
+
+        let progress = &self.progress;
+        w.field("level", progress.level);
+        w.field("level-progress", progress.level_progress);
+        w.field("score", progress.score);
+        w.field("lines", progress.lines);
+        w.field("combo", progress.combo);
+        w.field("pieces", progress.pieces);
+        w.duration("time", progress.time);
+        for &split in &progress.splits {
+            w.duration("split", split);
+        }
+        if let Some(replay) = &self.replay {
+            w.field("replay", storage::to_hex(&replay.encode()));
+        }
+        w.finish()
+    }
+
+    /// Continues a game saved with `to_snapshot`. It starts out paused, asking whether to continue.
+    pub fn from_snapshot(text: &str, piece_sets: Rc<[PieceSet]>) -> anyhow::Result<Game> {
+        let mut game = Game::restore_snapshot(text, piece_sets)?;
+        // Keys held when the game was saved are not held anymore, and the replay has to agree
+        game.release_all_actions();
+
+        game.state = GameState::Paused;
+        game.end_menu = Some(Menu::new(vec![
+            ("Continue last game", EndMenuOption::Resume),
+            ("New game", EndMenuOption::Title),
+        ]));
+        Ok(game)
+    }
+
+    /// Goes back to a point in a replay that is being played back, with the same keys held as then
+    pub fn from_checkpoint(text: &str, piece_sets: Rc<[PieceSet]>) -> anyhow::Result<Game> {
+        let mut game = Game::restore_snapshot(text, piece_sets)?;
+        game.replay = None;
+        game.playback = true;
+        game.state = GameState::Running;
+        Ok(game)
+    }
+
+    /// The game exactly as it was saved, except for the state, which is left to the caller
+    fn restore_snapshot(text: &str, piece_sets: Rc<[PieceSet]>) -> anyhow::Result<Game> {
+        let r = SnapshotReader::new(text)?;
+        let rules = read_rules(&r)?;
+        let piece_set = piece_sets
+            .get(rules.piece_set)
+            .filter(|set| set.name == r.get("piece-set-name").unwrap_or_default())
+            .context("the piece set of the saved game is not loaded")?;
+        let letters = piece_set.letters();
+        let is_piece = |letter: &char| letters.contains(letter);
+
+        let mut game = Game::new(rules, r.parse("seed")?, piece_sets.clone());
+        game.rng.set_word_pos(r.parse("rng")?);
+        if let Some(randomizer) = &mut game.randomizer {
+            randomizer.restore(r.get("randomizer-state")?)?;
+        }
+
+        let size = r.get("board")?;
+        let (width, height) = size
+            .split_once(' ')
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+            .filter(|&(w, h)| BOARD_WIDTHS.contains(&w) && BOARD_HEIGHTS.contains(&h))
+            .with_context(|| format!("invalid board: {size}"))?;
+        // The rules (and the puzzle) decide the size, so a different one means the save is broken
+        ensure!(
+            (width, height) == (game.board.width, game.board.height),
+            "the board size doesn't match the rules: {size}"
+        );
+        let mut board = Board::new(width, height, HIDDEN_ROWS);
+        for cell in r.all("cell") {
+            let parts: Vec<&str> = cell.split(' ').collect();
+            let [x, y, tile, locked_at] = parts[..] else {
+                anyhow::bail!("invalid cell: {cell}");
+            };
+            let pos = Pos::new(x.parse()?, y.parse()?);
+            let tile: char = tile.parse()?;
+            ensure!(board.contains(pos), "cell outside the board: {cell}");
+            ensure!(
+                tile == GARBAGE_TILE || is_piece(&tile),
+                "unknown tile: {cell}"
+            );
+            board.set_tile(pos, tile, TimeDelta::nanoseconds(locked_at.parse()?));
+        }
+        game.board = board;
+
+        game.moving_piece = match r.optional::<String>("piece")? {
+            Some(text) => {
+                let parts: Vec<&str> = text.split(' ').collect();
+                let [letter, rotation, x, y] = parts[..] else {
+                    anyhow::bail!("invalid piece: {text}");
+                };
+                let letter: char = letter.parse()?;
+                let rotation: u8 = rotation.parse()?;
+                ensure!(is_piece(&letter) && rotation < 4, "invalid piece: {text}");
+                let piece = Piece::new(letter, rotation, Pos::new(x.parse()?, y.parse()?));
+                ensure!(!game.piece_collides(piece), "the piece overlaps the board");
+                Some(piece)
+            }
+            None => None,
+        };
+        game.next_shapes = r.get("queue")?.chars().collect();
+        game.held_shape = r.optional("held")?;
+        ensure!(
+            game.next_shapes
+                .iter()
+                .chain(&game.held_shape)
+                .all(is_piece),
+            "unknown pieces in the queue"
+        );
+        game.hold_used = r.parse("hold-used")?;
+        game.auto_shift = AutoShift::read_snapshot(&r)?;
+        game.soft_drop_held = r.parse("soft-drop-held")?;
+        game.fall_progress = r.parse("fall-progress")?;
+        game.lock_timer.set_elapsed(r.duration("lock-timer")?);
+        game.lock_resets = r.parse("lock-resets")?;
+        game.last_kick = match r.optional::<String>("last-kick")? {
+            Some(text) => {
+                let parts: Vec<&str> = text.split(' ').collect();
+                let [x, y, half_turn] = parts[..] else {
+                    anyhow::bail!("invalid last-kick: {text}");
+                };
+                Some(Kick {
+                    offset: Pos::new(x.parse()?, y.parse()?),
+                    half_turn: half_turn.parse()?,
+                })
+            }
+            None => None,
+        };
+        game.lowest_row = r.parse("lowest-row")?;
+        game.post_game = r.parse("post-game")?;
+        game.garbage_hole = r.parse::<u8>("garbage-hole")?.min(width - 1);
+        game.garbage_timer.set_elapsed(r.duration("garbage-timer")?);
+
+        let progress = &mut game.progress;
+        progress.level = r.parse("level")?;
+        progress.level_progress = r.parse("level-progress")?;
+        progress.score = r.parse("score")?;
+        progress.lines = r.parse("lines")?;
+        progress.combo = r.parse("combo")?;
+        progress.pieces = r.parse("pieces")?;
+        progress.time = r.duration("time")?;
+        progress.splits = r
+            .all("split")
+            .map(|split| Ok(TimeDelta::nanoseconds(split.parse()?)))
+            .collect::<anyhow::Result<_>>()?;
+
+        game.replay = match r.optional::<String>("replay")? {
+            Some(hex) => Some(Replay::decode(&storage::from_hex(&hex)?).context("reading replay")?),
+            None => None,
+        };
+        Ok(game)
+    }
+
+    pub fn handle_action(&mut self, action: Action, is_pressed: bool) {
+        // Releases are handled even when the game is not running, so that no keys get stuck
+        if !is_pressed {
+            self.record(ReplayEvent::Release(action));
+            match action {
+                Action::MoveLeft => self.auto_shift.release(-1),
+                Action::MoveRight => self.auto_shift.release(1),
+                Action::SoftDrop => self.soft_drop_held = false,
+                _ => {}
+            }
+            return;
+        }
+
+        if self.state != GameState::Running {
+            return;
+        }
+        self.record(ReplayEvent::Press(action));
+
+        match action {
+            Action::MoveLeft => {
+                self.auto_shift.press(-1);
+                self.shift_moving_piece(-1, 1);
+            }
+            Action::MoveRight => {
+                self.auto_shift.press(1);
+                self.shift_moving_piece(1, 1);
+            }
+            Action::SoftDrop => {
+                self.soft_drop_held = true;
+                self.fall_progress = 0.0;
+                if self.rules.soft_drop == SoftDrop::Instant {
+                    let rows_dropped = self.drop_moving_piece_to_bottom();
+                    self.progress.add_drop(rows_dropped, false);
+                } else if let Some(piece) =
+                    self.try_update_moving_piece(|p| p.moved(Pos::new(0, 1)))
+                {
+                    self.move_piece_down_to(piece);
+                    self.progress.add_drop(1, false);
                 }
+            }
+            Action::HardDrop => {
+                let rows_dropped = self.drop_moving_piece_to_bottom();
+                self.progress.add_drop(rows_dropped, true);
 
                 self.handle_dropped_piece();
             }
-            _ => {}
+            Action::RotateCw => {
+                self.rotate_moving_piece(|p| p.rotated_cw());
+            }
+            Action::RotateCcw => {
+                self.rotate_moving_piece(|p| p.rotated_ccw());
+            }
+            Action::Rotate180 => {
+                if self.rules.rotation_180 {
+                    self.rotate_moving_piece(|p| p.rotated_180());
+                }
+            }
+            Action::Hold => self.hold_moving_piece(),
+        }
+    }
+
+    /// Moves the piece sideways up to `times` steps, stopping if something is in the way
+    fn shift_moving_piece(&mut self, direction: i8, times: u32) {
+        if direction == 0 {
+            return;
+        }
+        for _ in 0..times {
+            let Some(piece) = self.try_update_moving_piece(|p| p.moved(Pos::new(direction, 0)))
+            else {
+                break;
+            };
+            self.move_piece_to(piece);
+        }
+    }
+
+    /// Returns the number of rows the piece fell
+    fn drop_moving_piece_to_bottom(&mut self) -> u32 {
+        self.drop_moving_piece(u32::MAX)
+    }
+
+    /// Moves the piece down at most `max_rows` rows, and returns how far it got
+    fn drop_moving_piece(&mut self, max_rows: u32) -> u32 {
+        let Some(mut piece) = self.moving_piece else {
+            return 0;
+        };
+        let mut rows_dropped = 0;
+        while rows_dropped < max_rows {
+            let below = piece.moved(Pos::new(0, 1));
+            if self.piece_collides(below) {
+                break;
+            }
+            piece = below;
+            rows_dropped += 1;
         }
+        if rows_dropped > 0 {
+            self.move_piece_down_to(piece);
+        }
+        rows_dropped
     }
 
     fn try_update_moving_piece(&mut self, update_fn: impl FnOnce(Piece) -> Piece) -> Option<Piece> {
@@ -125,44 +880,311 @@ impl Game {
         None
     }
 
+    fn rotate_moving_piece(&mut self, rotate_fn: impl FnOnce(Piece) -> Piece) {
+        if let Some((piece, kick)) = self.try_rotate_moving_piece(rotate_fn) {
+            self.move_piece_to(piece);
+            self.last_kick = Some(kick);
+        }
+    }
+
+    /// Rotates the piece using the first wall kick that makes it fit, and returns which kick it was
+    fn try_rotate_moving_piece(
+        &self,
+        rotate_fn: impl FnOnce(Piece) -> Piece,
+    ) -> Option<(Piece, Kick)> {
+        let piece = self.moving_piece?;
+        let rotated = rotate_fn(piece);
+        let kicks = self.shapes[&piece.letter]
+            .kicks()
+            .kicks(piece.rotation, rotated.rotation);
+        let half_turn = (rotated.rotation + 4 - piece.rotation) % 4 == 2;
+
+        kicks
+            .iter()
+            .map(|&(dx, dy)| Pos::new(dx, dy))
+            .find(|&offset| !self.piece_collides(rotated.moved(offset)))
+            .map(|offset| (rotated.moved(offset), Kick { offset, half_turn }))
+    }
+
+    /// Uses the 3-corner rule: a T piece that was just rotated into a spot where at least three of the
+    /// corners around its center are blocked is a T-spin. It's only a mini T-spin if one of the two
+    /// corners it points towards is open, unless a 90° turn got it there using the kick that moves
+    /// it one column and two rows.
+    fn t_spin_kind(&self, piece: Piece) -> TSpin {
+        let Some(kick) = self.last_kick else {
+            return TSpin::None;
+        };
+        let shape = &self.shapes[&piece.letter];
+        if piece.letter != 'T' || shape.size() != 3 || shape.cell_count() != 4 {
+            return TSpin::None;
+        }
+
+        let center = piece.origin + Pos::new(1, 1);
+        let is_blocked = |dx, dy| {
+            let pos = center + Pos::new(dx, dy);
+            !self.board.contains(pos) || self.board.get_tile(pos).is_some()
+        };
+
+        // Clockwise from the top left, so that the corners it points towards come first after rotating
+        let corners = [(-1, -1), (1, -1), (1, 1), (-1, 1)];
+        let blocked = corners.map(|(dx, dy)| is_blocked(dx, dy));
+        let rotation = piece.rotation as usize;
+        let front = blocked[rotation] as u8 + blocked[(rotation + 1) % 4] as u8;
+        let back = blocked[(rotation + 2) % 4] as u8 + blocked[(rotation + 3) % 4] as u8;
+
+        if front + back < 3 {
+            TSpin::None
+        } else if front == 2 || kick.is_t_spin_triple_kick() {
+            TSpin::Full
+        } else {
+            TSpin::Mini
+        }
+    }
+
+    /// Swaps the moving piece with the held one, or with the next piece if nothing is held yet
+    fn hold_moving_piece(&mut self) {
+        if self.hold_used {
+            return;
+        }
+        // With a fixed queue there might be nothing to take the place of the held piece
+        if self.held_shape.is_none() && self.next_shapes.is_empty() {
+            return;
+        }
+        let Some(piece) = self.moving_piece.take() else {
+            return;
+        };
+
+        self.hold_used = true;
+        if let Some(letter) = self
+            .held_shape
+            .replace(piece.letter)
+            .or_else(|| self.take_next_shape())
+        {
+            self.spawn_piece(letter);
+        }
+    }
+
+    fn take_next_shape(&mut self) -> Option<char> {
+        let letter = self.next_shapes.pop_front();
+        self.fill_next_shapes();
+        letter
+    }
+
+    fn fill_next_shapes(&mut self) {
+        let Some(randomizer) = &mut self.randomizer else {
+            return;
+        };
+        while self.next_shapes.len() < self.rules.preview_count as usize {
+            let letter = randomizer.next_shape(&mut self.rng);
+            self.next_shapes.push_back(letter);
+        }
+    }
+
+    /// Used after a successful move or rotation, which restarts the lock delay if the piece is resting
+    fn move_piece_to(&mut self, piece: Piece) {
+        let was_resting = self.moving_piece_is_resting();
+        self.move_piece_down_to(piece);
+
+        if (was_resting || self.moving_piece_is_resting())
+            && self.lock_resets < self.rules.max_lock_resets
+        {
+            self.lock_resets += 1;
+            self.lock_timer.reset();
+        }
+    }
+
+    /// Used when the piece falls, which doesn't count as a lock reset
+    fn move_piece_down_to(&mut self, piece: Piece) {
+        self.moving_piece = Some(piece);
+        self.last_kick = None;
+
+        if piece.origin.y > self.lowest_row {
+            self.lowest_row = piece.origin.y;
+            self.lock_resets = 0;
+        }
+    }
+
+    fn moving_piece_is_resting(&self) -> bool {
+        self.moving_piece
+            .is_some_and(|p| self.piece_collides(p.moved(Pos::new(0, 1))))
+    }
+
+    /// Pieces spawn just above the visible rows, and then fall one row right away if they can
+    fn spawn_piece(&mut self, letter: char) {
+        let size = self.shapes[&letter].size();
+        let x = (self.board.width as i8 - size) / 2;
+        let mut piece = Piece::new(letter, 0, Pos { x, y: -2 });
+
+        // Block out: the new piece overlaps the stack
+        if self.piece_collides(piece) {
+            self.moving_piece = Some(piece);
+            self.end(GameState::GameOver);
+            return;
+        }
+
+        let below = piece.moved(Pos::new(0, 1));
+        if !self.piece_collides(below) {
+            piece = below;
+        }
+
+        self.moving_piece = Some(piece);
+        self.fall_progress = 0.0;
+        self.lock_timer.reset();
+        self.lock_resets = 0;
+        self.lowest_row = piece.origin.y;
+        self.last_kick = None;
+    }
+
     fn handle_dropped_piece(&mut self) {
+        let mut t_spin = TSpin::None;
+        let mut locked_out = false;
         if let Some(piece) = self.moving_piece.take() {
-            for pos in piece.tiles(&self.shapes) {
-                self.board.set_tile(pos, piece.letter);
+            t_spin = self.t_spin_kind(piece);
+            let tiles = piece.tiles(&self.shapes);
+            locked_out = self.rules.top_out.is_locked_out(&tiles);
+            for pos in tiles {
+                self.board.set_tile(pos, piece.letter, self.progress.time);
             }
         }
+        self.hold_used = false;
+        self.last_kick = None;
+
+        let clear = LineClear {
+            rows: self.board.remove_full_rows(),
+            t_spin,
+        };
+        self.progress.add_clear(clear);
+        self.progress.pieces += 1;
+
+        if let Some(split_lines) = self.rules.mode.split_lines() {
+            let splits = &mut self.progress.splits;
+            while self.progress.lines >= (splits.len() as u32 + 1) * split_lines {
+                splits.push(self.progress.time);
+            }
+        }
+
+        if locked_out {
+            self.end(GameState::GameOver);
+            return;
+        }
+
+        if self.goal_reached(clear) {
+            self.end(GameState::Won);
+            return;
+        }
 
-        self.progress.add_rows(self.board.remove_full_rows());
+        if let Some(name) = clear.name() {
+            self.clear_message = Some(name);
+            self.clear_message_timer.reset();
+        }
+    }
+
+    fn goal_reached(&self, clear: LineClear) -> bool {
+        if self.post_game {
+            return false;
+        }
+        if let Some(puzzle) = &self.puzzle {
+            return match puzzle.goal {
+                PuzzleGoal::ClearLines(lines) => self.progress.lines >= lines,
+                PuzzleGoal::PerfectClear => clear.rows > 0 && self.board.is_empty(),
+                PuzzleGoal::TSpinDouble => clear.rows == 2 && clear.t_spin == TSpin::Full,
+            };
+        }
+        if self.rules.mode.starts_with_garbage() {
+            return self.board.garbage_rows() == 0;
+        }
+        // Only topping out ends these, however far the player gets
+        if self.rules.mode.ends_at_top_out() {
+            return false;
+        }
+        match self.rules.mode.line_goal() {
+            Some(goal) => self.progress.lines >= goal,
+            None => self.progress.level >= self.progress.levels_to_win,
+        }
     }
 
     pub fn update(&mut self, time_passed: TimeDelta) {
         if self.state != GameState::Running {
             return;
         }
+        self.record(ReplayEvent::Update(time_passed));
 
-        self.moving_piece_timer.advance(time_passed);
-        while self.moving_piece_timer.tick(self.time_between_moves()) {
-            if let Some(piece) = self.try_update_moving_piece(|p| p.moved(Pos::new(0, 1))) {
-                self.moving_piece = Some(piece);
-            } else {
-                self.handle_dropped_piece();
+        self.progress.time += time_passed;
+
+        if self.time_left() == Some(TimeDelta::zero()) {
+            self.progress.time = TimeDelta::seconds(self.rules.time_limit_secs as i64);
+            self.end(GameState::Won);
+            return;
+        }
+
+        if self.clear_message.is_some() {
+            self.clear_message_timer.advance(time_passed);
+            if self.clear_message_timer.tick(TimeDelta::milliseconds(1500)) {
+                self.clear_message = None;
             }
         }
-        if self.moving_piece.is_none()
-            && let Some(letter) = self.next_shape.take()
-        {
-            let piece = Piece::new(letter, 0, Pos { x: 4, y: 1 });
-            self.moving_piece = Some(piece);
-            self.moving_piece_timer.reset();
 
-            if self.piece_collides(piece) {
-                self.state = GameState::GameOver;
+        if let Some(interval) = self.rules.mode.garbage_interval(self.progress.level) {
+            self.garbage_timer.advance(time_passed);
+            while self.garbage_timer.tick(interval) {
+                self.add_garbage_row();
+                if self.state != GameState::Running {
+                    return;
+                }
+            }
+        }
+
+        let das = TimeDelta::milliseconds(self.rules.das_ms as i64);
+        let arr = TimeDelta::milliseconds(self.rules.arr_ms as i64);
+        let shifts = self.auto_shift.update(time_passed, das, arr);
+        self.shift_moving_piece(self.auto_shift.direction(), shifts);
+
+        let gravity = self.rows_per_second();
+        let fall_speed = match self.rules.soft_drop {
+            _ if !self.soft_drop_held => gravity,
+            SoftDrop::Factor(factor) => gravity * factor.max(1) as f64,
+            SoftDrop::Instant => {
+                let rows_dropped = self.drop_moving_piece_to_bottom();
+                self.progress.add_drop(rows_dropped, false);
+                gravity
+            }
+        };
+
+        // Several rows can be fallen at once when the gravity is high
+        self.fall_progress += fall_speed * time_passed.as_seconds_f64();
+        let rows_to_fall = if self.fall_progress.is_finite() {
+            let rows = self.fall_progress.floor();
+            self.fall_progress -= rows;
+            rows.min(u32::MAX as f64) as u32
+        } else {
+            u32::MAX
+        };
+        if rows_to_fall > 0 {
+            let rows_fallen = self.drop_moving_piece(rows_to_fall);
+            if self.soft_drop_held {
+                self.progress.add_drop(rows_fallen, false);
+            }
+            if rows_fallen < rows_to_fall {
+                // The piece is resting, so the lock delay decides when it stops
+                self.fall_progress = 0.0;
+            }
+        }
+
+        if self.moving_piece_is_resting() {
+            self.lock_timer.advance(time_passed);
+            let lock_delay = TimeDelta::milliseconds(self.rules.lock_delay_ms as i64);
+            if self.lock_timer.tick(lock_delay) {
+                self.handle_dropped_piece();
             }
+        } else {
+            self.lock_timer.reset();
         }
-        if self.next_shape.is_none() {
-            self.next_shape = Some(random_shape(
-                &self.shapes.keys().cloned().collect::<Vec<char>>(),
-            ));
+        if self.moving_piece.is_none() && self.state == GameState::Running {
+            // The held piece is the last one to be played when the queue runs out
+            match self.take_next_shape().or_else(|| self.held_shape.take()) {
+                Some(letter) => self.spawn_piece(letter),
+                None => self.end(GameState::GameOver),
+            }
         }
     }
 
@@ -174,8 +1196,79 @@ impl Game {
     }
 }
 
-fn random_shape(values: &[char]) -> char {
-    values[rand::rng().random_range(0..values.len())]
+/// The option after `current`, going back to the first one after the last
+fn next_option<T: PartialEq + Copy>(current: T, options: &[T]) -> T {
+    let idx = options.iter().position(|&o| o == current);
+    options[idx.map_or(0, |i| (i + 1) % options.len())]
+}
+
+/// Where the best result is saved. Some of the settings are part of the key, so that for example
+/// a longer Ultra game doesn't compete with a shorter one.
+fn record_key(rules: &Rules, piece_set: &PieceSet, record: Record) -> String {
+    let mut key = format!(
+        "best-{}-{}-{}x{}-{}-{:08x}",
+        record.name(),
+        rules.mode.name().to_lowercase(),
+        rules.board_width,
+        rules.board_height,
+        key_part(&piece_set.name),
+        piece_set.fingerprint,
+    );
+    if rules.mode.has_time_limit() {
+        key.push_str(&format!("-{}s", rules.time_limit_secs));
+    }
+    if rules.mode.starts_with_garbage() {
+        key.push_str(&format!("-{}rows", rules.cheese_rows));
+        key.push_str(&format!("-{}pct", rules.cheese_messiness));
+    }
+    if record == Record::Score {
+        key.push_str(&format!("-{}", key_part(rules.scoring.name())));
+    }
+    key
+}
+
+/// A name that is safe to use in a storage key, which can be a file name
+fn key_part(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+fn load_record(rules: &Rules, piece_set: &PieceSet, record: Record) -> Option<i64> {
+    if rules.mode.record() != Some(record) {
+        return None;
+    }
+    storage::load(&record_key(rules, piece_set, record))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Something the game can't do on its own, so it asks the app to do it
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GameRequest {
+    /// Start a new game with the same rules, right away
+    Restart {
+        new_seed: bool,
+    },
+    ReturnToTitle,
+    /// Play back the replay of this game if it is over, or else the last finished game
+    WatchReplay,
+    Quit,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EndMenuOption {
+    KeepPlaying,
+    NextPuzzle,
+    /// Continues a saved game
+    Resume,
+    WatchReplay,
+    Retry,
+    RetryWithNewSeed,
+    Title,
+    Quit,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -184,82 +1277,153 @@ pub enum GameState {
     Running,
     Paused,
     GameOver,
+    /// The last level was reached. The game can still continue in post-game.
+    Won,
 }
 
 pub struct Board {
-    pub tiles: Vec<Vec<char>>,
+    /// Starts with the hidden rows, followed by the visible ones
+    cells: Vec<Vec<Cell>>,
     pub width: u8,
+    /// The number of visible rows
     pub height: u8,
+    /// Rows above the visible ones that pieces can spawn, rotate and lock in, but that are not drawn.
+    /// They have negative `y` coordinates.
+    pub hidden_rows: u8,
+}
+
+#[derive(Clone, Copy)]
+struct Cell {
+    tile: char,
+    /// The game time when the tile was placed
+    locked_at: TimeDelta,
 }
 
 const EMPTY_TILE: char = 0 as char;
 
+const EMPTY_CELL: Cell = Cell {
+    tile: EMPTY_TILE,
+    locked_at: TimeDelta::zero(),
+};
+
+/// Cells that were not placed by the player, but added to the board by the game
+pub const GARBAGE_TILE: char = '#';
+
 impl Board {
+    fn cell(&self, pos: Pos) -> &Cell {
+        let row = (pos.y as isize + self.hidden_rows as isize) as usize;
+        &self.cells[row][pos.x as usize]
+    }
+
+    fn cell_mut(&mut self, pos: Pos) -> &mut Cell {
+        let row = (pos.y as isize + self.hidden_rows as isize) as usize;
+        &mut self.cells[row][pos.x as usize]
+    }
+
     pub fn get_tile(&self, pos: Pos) -> Option<char> {
-        Some(self.tiles[pos.y as usize][pos.x as usize]).filter(|&t| t != EMPTY_TILE)
+        Some(self.cell(pos).tile).filter(|&t| t != EMPTY_TILE)
     }
 
-    pub fn set_tile(&mut self, pos: Pos, tile: char) {
-        self.tiles[pos.y as usize][pos.x as usize] = tile;
+    /// When the tile at the position was placed, in game time
+    pub fn locked_at(&self, pos: Pos) -> TimeDelta {
+        self.cell(pos).locked_at
     }
 
-    pub fn clear_tile(&mut self, pos: Pos) {
-        self.tiles[pos.y as usize][pos.x as usize] = EMPTY_TILE;
+    pub fn set_tile(&mut self, pos: Pos, tile: char, locked_at: TimeDelta) {
+        *self.cell_mut(pos) = Cell { tile, locked_at };
     }
 
+    /// Whether the position is on the board, including the hidden rows
     pub fn contains(&self, pos: Pos) -> bool {
-        let w = self.width;
-        let h = self.height;
         let Pos { x, y } = pos;
-        if x < 0 || y < 0 {
-            return false;
-        }
-        let (x, y) = (x as u8, y as u8);
-        if x >= w || y >= h {
-            return false;
+        x >= 0 && x < self.width as i8 && y >= -(self.hidden_rows as i8) && y < self.height as i8
+    }
+
+    pub fn is_visible(&self, pos: Pos) -> bool {
+        self.contains(pos) && pos.y >= 0
+    }
+
+    /// Pushes everything up one row, and fills the bottom row with garbage except for the hole.
+    /// Returns whether anything was pushed out of the top.
+    pub fn insert_garbage_row(&mut self, hole: u8, locked_at: TimeDelta) -> bool {
+        let garbage = Cell {
+            tile: GARBAGE_TILE,
+            locked_at,
+        };
+        let mut row = vec![garbage; self.width as usize];
+        row[hole as usize] = EMPTY_CELL;
+        let top_row = self.cells.remove(0);
+        self.cells.push(row);
+        top_row.iter().any(|c| c.tile != EMPTY_TILE)
+    }
+
+    /// Whether there are no tiles on the board, including the hidden rows
+    pub fn is_empty(&self) -> bool {
+        self.cells
+            .iter()
+            .flatten()
+            .all(|cell| cell.tile == EMPTY_TILE)
+    }
+
+    /// A hash of every tile, including the hidden rows, that stays the same between versions
+    pub fn fingerprint(&self) -> u32 {
+        // FNV-1a
+        let mut hash: u32 = 0x811c9dc5;
+        for cell in self.cells.iter().flatten() {
+            for byte in (cell.tile as u32).to_le_bytes() {
+                hash = (hash ^ byte as u32).wrapping_mul(0x01000193);
+            }
         }
-        true
+        hash
+    }
+
+    /// The number of rows with garbage in them
+    pub fn garbage_rows(&self) -> usize {
+        self.cells
+            .iter()
+            .filter(|row| row.iter().any(|c| c.tile == GARBAGE_TILE))
+            .count()
     }
 
     pub fn remove_full_rows(&mut self) -> u8 {
-        let mut removed_rows = 0;
-        for y in (0..self.height).rev() {
-            let mut full_row = true;
-            for x in 0..self.width {
-                if self.get_tile(Pos::new(x as i8, y as i8)).is_none() {
-                    full_row = false;
-                    break;
-                }
-            }
-            if full_row {
-                removed_rows += 1;
-            } else {
-                for x in 0..self.width {
-                    if let Some(tile) = self.get_tile(Pos::new(x as i8, y as i8)) {
-                        self.set_tile(Pos::new(x as i8, (y + removed_rows) as i8), tile);
-                    }
-                }
-            }
-            if removed_rows > 0 {
-                for x in 0..self.width {
-                    self.clear_tile(Pos::new(x as i8, y as i8));
-                }
-            }
+        let rows_before = self.cells.len();
+        self.cells
+            .retain(|row| row.iter().any(|c| c.tile == EMPTY_TILE));
+        let removed_rows = rows_before - self.cells.len();
+        for _ in 0..removed_rows {
+            self.cells.insert(0, vec![EMPTY_CELL; self.width as usize]);
         }
-        removed_rows
+        removed_rows as u8
     }
 }
 
 impl Board {
-    pub fn new(width: u8, height: u8) -> Board {
+    pub fn new(width: u8, height: u8, hidden_rows: u8) -> Board {
         Board {
-            tiles: vec![vec![EMPTY_TILE; width as usize]; height as usize],
+            cells: vec![vec![EMPTY_CELL; width as usize]; (hidden_rows + height) as usize],
             width,
             height,
+            hidden_rows,
         }
     }
 }
 
+/// How a rotation was made to fit
+#[derive(Debug, Clone, Copy)]
+struct Kick {
+    /// How far the rotated piece was moved
+    offset: Pos,
+    /// Whether it was a 180° turn, which has its own kicks
+    half_turn: bool,
+}
+
+impl Kick {
+    /// The last kick of a 90° turn, one column and two rows, which makes any T-spin a full one
+    fn is_t_spin_triple_kick(self) -> bool {
+        !self.half_turn && self.offset.x.abs() == 1 && self.offset.y.abs() == 2
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Piece {
     pub letter: char,
@@ -286,7 +1450,17 @@ impl Piece {
         self
     }
 
-    pub fn tiles<'a, S>(&'a self, shapes: &S) -> [Pos; 4]
+    pub fn rotated_ccw(mut self) -> Self {
+        self.rotation = (self.rotation + 3) % 4;
+        self
+    }
+
+    pub fn rotated_180(mut self) -> Self {
+        self.rotation = (self.rotation + 2) % 4;
+        self
+    }
+
+    pub fn tiles<'a, S>(&'a self, shapes: &S) -> Vec<Pos>
     where
         S: Index<&'a char, Output = Shape>,
     {
@@ -294,7 +1468,7 @@ impl Piece {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy)]
 pub struct Pos {
     pub x: i8,
     pub y: i8,
@@ -317,55 +1491,159 @@ impl Add<Pos> for Pos {
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct Shape([Pos; 4]);
+#[derive(Debug, Clone)]
+pub struct Shape {
+    offsets: Vec<Pos>,
+    /// The side of the square the piece rotates within, so the rotation center is in its middle
+    size: i8,
+    kicks: KickTable,
+}
 
 impl Shape {
-    pub fn new(offsets: [Pos; 4]) -> Self {
-        Self(offsets)
+    pub fn new(offsets: Vec<Pos>, size: i8, kicks: KickTable) -> Self {
+        Self {
+            offsets,
+            size,
+            kicks,
+        }
     }
 
-    pub fn at(self, pos: Pos) -> [Pos; 4] {
-        self.0.map(|off| pos + off)
+    pub fn size(&self) -> i8 {
+        self.size
     }
 
-    pub fn rotated_once(self) -> Self {
-        Self(self.0.map(|Pos { x, y }| Pos { x: -y, y: x }))
+    pub fn kicks(&self) -> KickTable {
+        self.kicks
     }
 
-    pub fn rotated(mut self, times: u8) -> Self {
-        for _ in 0..times {
-            self = self.rotated_once();
+    pub fn cell_count(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn at(&self, pos: Pos) -> Vec<Pos> {
+        self.offsets.iter().map(|&off| pos + off).collect()
+    }
+
+    /// Rotates the piece clockwise around the center of its square
+    pub fn rotated_once(mut self) -> Self {
+        let size = self.size;
+        for off in &mut self.offsets {
+            *off = Pos {
+                x: size - 1 - off.y,
+                y: off.x,
+            };
         }
         self
     }
+
+    pub fn rotated(&self, times: u8) -> Self {
+        let mut shape = self.clone();
+        for _ in 0..times {
+            shape = shape.rotated_once();
+        }
+        shape
+    }
 }
 
 pub struct GameProgress {
     pub levels_to_win: u8,
     pub level: u8,
+    pub score: u32,
+    pub lines: u32,
+    /// Number of pieces in a row that have cleared at least one row
+    pub combo: u32,
+    /// Number of pieces that have locked
+    pub pieces: u32,
+    /// Time spent playing, not counting pauses
+    pub time: TimeDelta,
+    /// The time when each split was reached, in modes that have them
+    pub splits: Vec<TimeDelta>,
 
+    scoring: ScoringTable,
+    /// 0 means that the level never changes
     rows_per_level: u8,
     level_progress: u8,
 }
 
 impl GameProgress {
-    pub fn new(levels_to_win: u8, rows_per_level: u8) -> Self {
+    pub fn new(levels_to_win: u8, rows_per_level: u8, scoring: ScoringTable) -> Self {
         Self {
             levels_to_win,
             level: 0,
+            score: 0,
+            lines: 0,
+            combo: 0,
+            pieces: 0,
+            time: TimeDelta::zero(),
+            splits: Vec::new(),
 
+            scoring,
             rows_per_level,
             level_progress: 0,
         }
     }
 
-    pub fn add_rows(&mut self, count: u8) {
+    /// Should be called every time a piece locks, even if no rows were cleared
+    pub fn add_clear(&mut self, clear: LineClear) {
+        let count = clear.rows;
+        if count == 0 {
+            self.combo = 0;
+            self.score += self.scoring.line_clear_points(clear, 0, self.level);
+            return;
+        }
+
+        self.combo += 1;
+        self.score += self
+            .scoring
+            .line_clear_points(clear, self.combo, self.level);
+        self.lines += count as u32;
+
         self.level_progress += count;
 
-        while self.level_progress >= self.rows_per_level {
+        while self.rows_per_level > 0 && self.level_progress >= self.rows_per_level {
             self.level_progress -= self.rows_per_level;
-            self.level += 1;
+            self.level = self.level.saturating_add(1);
+        }
+    }
+
+    pub fn add_drop(&mut self, rows: u32, hard_drop: bool) {
+        self.score += self.scoring.drop_points(rows, hard_drop);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_restore_every_field() {
+        let piece_sets: Rc<[PieceSet]> = PieceSet::builtin().into();
+        for mode in GameMode::ALL {
+            let rules = Rules {
+                mode,
+                visibility: Visibility::Fading,
+                ..Rules::default()
+            };
+            let mut game = Game::new(rules, 7, piece_sets.clone());
+            game.start();
+            let frame = TimeDelta::nanoseconds(16_666_667);
+            for i in 0..150 {
+                match i {
+                    10 => game.handle_action(Action::MoveRight, true),
+                    20 => game.handle_action(Action::RotateCw, true),
+                    30 => game.handle_action(Action::Hold, true),
+                    // Puzzles end when their few pieces run out
+                    60 if game.puzzle.is_none() => game.handle_action(Action::HardDrop, true),
+                    90 => game.handle_action(Action::SoftDrop, true),
+                    _ => {}
+                }
+                game.update(frame);
+            }
+            assert!(game.in_progress(), "{} ended too early", mode.name());
+
+            let snapshot = game.to_snapshot();
+            let restored = Game::restore_snapshot(&snapshot, piece_sets.clone()).unwrap();
+            assert_eq!(restored.to_snapshot(), snapshot, "{}", mode.name());
         }
     }
 }
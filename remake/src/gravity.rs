@@ -0,0 +1,84 @@
+/// The frame rate of the NES, which its gravity is counted in
+const NES_FRAMES_PER_SECOND: f64 = 60.0988;
+
+/// Frames per row on the NES for levels 0 to 28. It is 1 from level 29.
+const NES_FRAMES_PER_ROW: [u8; 29] = [
+    48, 43, 38, 33, 28, 23, 18, 13, 8, 6, 5, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+];
+
+/// 20G means 20 rows per frame at 60 frames per second, which is the fastest gravity in the
+/// guideline
+const MAX_ROWS_PER_SECOND: f64 = 20.0 * 60.0;
+
+/// How fast pieces fall depending on the level
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum GravityCurve {
+    /// 800 ms per row at level 0 down to none at the last level, speeding up faster in the beginning
+    #[default]
+    Martomate,
+    /// The formula from the original Java version, which is the same curve in whole milliseconds
+    Java,
+    /// The frames per row table from NES Tetris
+    Nes,
+    /// `(0.8 - (level - 1) * 0.007) ^ (level - 1)` seconds per row, counting levels from 1
+    Guideline,
+    /// 20 rows per frame, so pieces fall to the bottom right away on a normal board
+    TwentyG,
+}
+
+impl GravityCurve {
+    pub const ALL: [GravityCurve; 5] = [
+        GravityCurve::Martomate,
+        GravityCurve::Java,
+        GravityCurve::Nes,
+        GravityCurve::Guideline,
+        GravityCurve::TwentyG,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GravityCurve::Martomate => "Martomate",
+            GravityCurve::Java => "Java",
+            GravityCurve::Nes => "NES",
+            GravityCurve::Guideline => "Guideline",
+            GravityCurve::TwentyG => "20G",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&g| g == self).unwrap();
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+
+    /// How many rows the moving piece falls per second, which may be infinite
+    pub fn rows_per_second(self, level: u8, levels_to_win: u8) -> f64 {
+        match self {
+            GravityCurve::Martomate => {
+                use std::f32::consts::PI;
+
+                let progress = (level as f32 / levels_to_win as f32).min(1.0);
+                let speed_up = (progress * PI / 2.0).sin();
+                let ms = ((1.0 - speed_up) * 800.0) as i64;
+                1000.0 / ms as f64
+            }
+            GravityCurve::Java => {
+                use std::f64::consts::PI;
+
+                let level = level.min(levels_to_win) as f64;
+                let angle = PI / 2.0 / levels_to_win as f64 * level - PI / 2.0;
+                let ms = (800.0 - angle.cos() * 800.0) as i64;
+                1000.0 / ms as f64
+            }
+            GravityCurve::Nes => {
+                let frames = NES_FRAMES_PER_ROW.get(level as usize).copied().unwrap_or(1);
+                NES_FRAMES_PER_SECOND / frames as f64
+            }
+            GravityCurve::Guideline => {
+                let n = level as f64;
+                let seconds = (0.8 - n * 0.007).max(0.0).powf(n);
+                (1.0 / seconds).min(MAX_ROWS_PER_SECOND)
+            }
+            GravityCurve::TwentyG => MAX_ROWS_PER_SECOND,
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::ensure;
+use chrono::TimeDelta;
+use winit::keyboard::KeyCode;
+
+use crate::{
+    snapshot::{SnapshotReader, SnapshotWriter},
+    time::Timer,
+};
+
+/// Something the player does to the moving piece, independent of which key was used
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+    Hold,
+}
+
+impl Action {
+    pub const ALL: [Action; 8] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::RotateCw,
+        Action::RotateCcw,
+        Action::Rotate180,
+        Action::Hold,
+    ];
+
+    /// A short name for the input display of replays
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "Left",
+            Action::MoveRight => "Right",
+            Action::SoftDrop => "Down",
+            Action::HardDrop => "Drop",
+            Action::RotateCw => "CW",
+            Action::RotateCcw => "CCW",
+            Action::Rotate180 => "180",
+            Action::Hold => "Hold",
+        }
+    }
+
+    pub fn from_key(code: KeyCode) -> Option<Action> {
+        Some(match code {
+            KeyCode::ArrowLeft => Action::MoveLeft,
+            KeyCode::ArrowRight => Action::MoveRight,
+            KeyCode::ArrowDown => Action::SoftDrop,
+            KeyCode::KeyD => Action::HardDrop,
+            KeyCode::ArrowUp | KeyCode::KeyX => Action::RotateCw,
+            KeyCode::KeyZ | KeyCode::ControlLeft | KeyCode::ControlRight => Action::RotateCcw,
+            KeyCode::KeyA => Action::Rotate180,
+            KeyCode::KeyC | KeyCode::ShiftLeft | KeyCode::ShiftRight => Action::Hold,
+            _ => return None,
+        })
+    }
+}
+
+/// Keeps track of the left and right keys to repeat sideways moves while they are held.
+///
+/// The first move happens when the key is pressed, then nothing happens until the
+/// Delayed Auto Shift (DAS) has passed, after which the piece moves once every
+/// Auto Repeat Rate (ARR).
+pub struct AutoShift {
+    left_held: bool,
+    right_held: bool,
+    /// -1 for left, 1 for right and 0 if no key is held. The last pressed key wins.
+    direction: i8,
+    timer: Timer,
+    charged: bool,
+}
+
+impl AutoShift {
+    pub fn new() -> Self {
+        Self {
+            left_held: false,
+            right_held: false,
+            direction: 0,
+            timer: Timer::new(),
+            charged: false,
+        }
+    }
+
+    pub fn direction(&self) -> i8 {
+        self.direction
+    }
+
+    /// Saves which keys are held and how far the DAS has come, for `Game::to_snapshot`
+    pub fn write_snapshot(&self, w: &mut SnapshotWriter) {
+        w.field("das-left-held", self.left_held);
+        w.field("das-right-held", self.right_held);
+        w.field("das-dir", self.direction);
+        w.duration("das-timer", self.timer.elapsed());
+        w.field("das-charged", self.charged);
+    }
+
+    pub fn read_snapshot(r: &SnapshotReader) -> anyhow::Result<Self> {
+        let direction = r.parse("das-dir")?;
+        ensure!((-1..=1).contains(&direction), "invalid das-dir");
+        let mut timer = Timer::new();
+        timer.set_elapsed(r.duration("das-timer")?);
+        Ok(Self {
+            left_held: r.parse("das-left-held")?,
+            right_held: r.parse("das-right-held")?,
+            direction,
+            timer,
+            charged: r.parse("das-charged")?,
+        })
+    }
+
+    pub fn press(&mut self, direction: i8) {
+        if direction < 0 {
+            self.left_held = true;
+        } else {
+            self.right_held = true;
+        }
+        self.start(direction);
+    }
+
+    pub fn release(&mut self, direction: i8) {
+        if direction < 0 {
+            self.left_held = false;
+        } else {
+            self.right_held = false;
+        }
+
+        if self.direction == direction {
+            match (self.left_held, self.right_held) {
+                (true, _) => self.start(-1),
+                (_, true) => self.start(1),
+                _ => self.start(0),
+            }
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        self.left_held = false;
+        self.right_held = false;
+        self.start(0);
+    }
+
+    fn start(&mut self, direction: i8) {
+        self.direction = direction;
+        self.timer.reset();
+        self.charged = false;
+    }
+
+    /// Returns how many times the piece should move, where `u32::MAX` means as far as possible
+    pub fn update(&mut self, time_passed: TimeDelta, das: TimeDelta, arr: TimeDelta) -> u32 {
+        if self.direction == 0 {
+            return 0;
+        }
+
+        self.timer.advance(time_passed);
+
+        let mut moves = 0;
+        if !self.charged {
+            if !self.timer.tick(das) {
+                return 0;
+            }
+            self.charged = true;
+            moves += 1;
+        }
+
+        if arr <= TimeDelta::zero() {
+            return u32::MAX;
+        }
+        while self.timer.tick(arr) {
+            moves += 1;
+        }
+        moves
+    }
+}
@@ -1,15 +1,118 @@
 mod app;
 mod canvas;
 mod game;
+mod gravity;
+mod input;
+mod menu;
+mod mode;
+mod pieces;
+mod playback;
+mod puzzle;
+mod randomizer;
 mod renderer;
+mod replay;
+mod rules;
+mod scoring;
+mod snapshot;
+mod srs;
+mod storage;
 mod texture;
 mod tile;
 mod time;
 
+use anyhow::Context;
+pub use rules::SoftDrop;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-pub fn run() -> anyhow::Result<()> {
+/// Settings that can be given on the command line
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The same seed always gives the same pieces. If none is given a random one is used.
+    pub seed: Option<u64>,
+    pub board_width: u8,
+    pub board_height: u8,
+    /// How long a game lasts in modes with a time limit
+    pub time_limit_secs: u32,
+    /// See `Rules::fade_delay_ms`
+    pub fade_delay_ms: u32,
+    /// See `Rules::lock_delay_ms`
+    pub lock_delay_ms: u32,
+    /// See `Rules::max_lock_resets`
+    pub max_lock_resets: u32,
+    /// See `Rules::das_ms`
+    pub das_ms: u32,
+    /// See `Rules::arr_ms`
+    pub arr_ms: u32,
+    /// See `Rules::soft_drop`
+    pub soft_drop: SoftDrop,
+    /// The contents of piece set files, in the format described in `pieces.rs`.
+    /// The first one is used by default.
+    pub piece_sets: Vec<String>,
+    /// The contents of a replay file to play back and check without opening a window
+    pub check_replay: Option<Vec<u8>>,
+    /// The contents of a replay file to watch instead of starting on the title screen
+    pub replay: Option<Vec<u8>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let rules = rules::Rules::default();
+        Self {
+            seed: None,
+            board_width: rules.board_width,
+            board_height: rules.board_height,
+            time_limit_secs: rules.time_limit_secs,
+            fade_delay_ms: rules.fade_delay_ms,
+            lock_delay_ms: rules.lock_delay_ms,
+            max_lock_resets: rules.max_lock_resets,
+            das_ms: rules.das_ms,
+            arr_ms: rules.arr_ms,
+            soft_drop: rules.soft_drop,
+            piece_sets: Vec::new(),
+            check_replay: None,
+            replay: None,
+        }
+    }
+}
+
+pub fn run(options: Options) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        rules::BOARD_WIDTHS.contains(&options.board_width),
+        "the board width must be within {:?}",
+        rules::BOARD_WIDTHS
+    );
+    anyhow::ensure!(
+        rules::BOARD_HEIGHTS.contains(&options.board_height),
+        "the board height must be within {:?}",
+        rules::BOARD_HEIGHTS
+    );
+    anyhow::ensure!(
+        options.time_limit_secs > 0,
+        "the time limit must be positive"
+    );
+
+    let mut piece_sets = pieces::PieceSet::builtin();
+    for (i, text) in options.piece_sets.iter().enumerate() {
+        let piece_set = pieces::PieceSet::parse(text)
+            .with_context(|| format!("loading piece set {}", i + 1))?;
+        piece_sets.push(piece_set);
+    }
+
+    if let Some(bytes) = &options.check_replay {
+        let replay = replay::Replay::decode(bytes).context("reading replay")?;
+        let game = replay.play(piece_sets.into())?;
+        print!("{}", game.summary());
+        if let Some(hash) = replay.board_hash {
+            anyhow::ensure!(
+                game.board.fingerprint() == hash,
+                "the board is not the same as when the replay was recorded"
+            );
+            println!("The board is the same as when the replay was recorded");
+        }
+        return Ok(());
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     {
         env_logger::init();
@@ -20,7 +123,7 @@ pub fn run() -> anyhow::Result<()> {
     }
 
     let event_loop = winit::event_loop::EventLoop::with_user_event().build()?;
-    let mut app = crate::app::App::new(&event_loop);
+    let mut app = crate::app::App::new(&event_loop, options, piece_sets.into());
     event_loop.run_app(&mut app)?;
 
     Ok(())
@@ -30,7 +133,7 @@ pub fn run() -> anyhow::Result<()> {
 #[wasm_bindgen(start)]
 pub fn run_web() -> Result<(), wasm_bindgen::JsValue> {
     console_error_panic_hook::set_once();
-    run().unwrap_throw();
+    run(Options::default()).unwrap_throw();
 
     Ok(())
 }
@@ -1,3 +1,66 @@
+use anyhow::{Context, bail};
+use tetris::Options;
+
 fn main() {
-    tetris::run().unwrap();
+    let options = parse_args(std::env::args().skip(1)).unwrap();
+    tetris::run(options).unwrap();
+}
+
+/// Reads the `Options` from the command line:
+///
+/// - `--seed <number>`
+/// - `--width <columns>` and `--height <rows>`
+/// - `--time-limit <seconds>` and `--fade-delay <milliseconds>`
+/// - `--lock-delay <milliseconds>` and `--lock-resets <count>`
+/// - `--das <milliseconds>`, `--arr <milliseconds>` and `--soft-drop <factor|instant>`
+/// - `--pieces <file>`, which can be given more than once
+/// - `--replay <file>` and `--check-replay <file>`
+fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Options> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("missing value for {arg}"))
+        };
+        match arg.as_str() {
+            "--seed" => options.seed = Some(value()?.parse().context("parsing seed")?),
+            "--width" => options.board_width = value()?.parse().context("parsing width")?,
+            "--height" => options.board_height = value()?.parse().context("parsing height")?,
+            "--time-limit" => {
+                options.time_limit_secs = value()?.parse().context("parsing time limit")?
+            }
+            "--fade-delay" => {
+                options.fade_delay_ms = value()?.parse().context("parsing fade delay")?
+            }
+            "--lock-delay" => {
+                options.lock_delay_ms = value()?.parse().context("parsing lock delay")?
+            }
+            "--lock-resets" => {
+                options.max_lock_resets = value()?.parse().context("parsing lock resets")?
+            }
+            "--das" => options.das_ms = value()?.parse().context("parsing DAS")?,
+            "--arr" => options.arr_ms = value()?.parse().context("parsing ARR")?,
+            "--soft-drop" => options.soft_drop = value()?.parse()?,
+            "--pieces" => {
+                let path = value()?;
+                let text = std::fs::read_to_string(&path)
+                    .with_context(|| format!("reading piece set from {path}"))?;
+                options.piece_sets.push(text);
+            }
+            "--replay" => {
+                let path = value()?;
+                let bytes =
+                    std::fs::read(&path).with_context(|| format!("reading replay from {path}"))?;
+                options.replay = Some(bytes);
+            }
+            "--check-replay" => {
+                let path = value()?;
+                let bytes =
+                    std::fs::read(&path).with_context(|| format!("reading replay from {path}"))?;
+                options.check_replay = Some(bytes);
+            }
+            _ => bail!("unknown argument: {arg}"),
+        }
+    }
+    Ok(options)
 }
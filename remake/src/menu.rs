@@ -0,0 +1,39 @@
+use winit::keyboard::KeyCode;
+
+/// A vertical list of options that can be chosen using the keyboard
+pub struct Menu<T> {
+    items: Vec<(&'static str, T)>,
+    selected: usize,
+}
+
+impl<T: Copy> Menu<T> {
+    pub fn new(items: Vec<(&'static str, T)>) -> Self {
+        Self { items, selected: 0 }
+    }
+
+    /// The text of every item, and whether it is the selected one
+    pub fn items(&self) -> impl Iterator<Item = (&'static str, bool)> + '_ {
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(i, &(text, _))| (text, i == self.selected))
+    }
+
+    /// Moves the selection with the arrow keys, and returns the chosen item when Enter or Space is pressed
+    pub fn handle_key(&mut self, code: KeyCode) -> Option<T> {
+        match code {
+            KeyCode::ArrowUp => {
+                self.selected = (self.selected + self.items.len() - 1) % self.items.len();
+                None
+            }
+            KeyCode::ArrowDown => {
+                self.selected = (self.selected + 1) % self.items.len();
+                None
+            }
+            KeyCode::Enter | KeyCode::NumpadEnter | KeyCode::Space => {
+                self.items.get(self.selected).map(|&(_, item)| item)
+            }
+            _ => None,
+        }
+    }
+}
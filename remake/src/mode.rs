@@ -0,0 +1,137 @@
+use chrono::TimeDelta;
+
+/// What the player is trying to do, and what ends the game other than topping out
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum GameMode {
+    /// Clear lines to reach the last level, and then keep going if you want
+    #[default]
+    Marathon,
+    /// Clear 40 lines as fast as possible
+    Sprint,
+    /// Get as many points as possible before the time runs out
+    Ultra,
+    /// Clear the garbage rows the board starts with as fast as possible
+    Dig,
+    /// Survive for as long as possible while garbage rows rise from the bottom
+    Survival,
+    /// Reach the goal of a puzzle with a fixed board and sequence of pieces
+    Puzzle,
+}
+
+/// What makes one finished game better than another
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Record {
+    /// The lowest time
+    Time,
+    /// The highest score
+    Score,
+    /// The longest time
+    TimeSurvived,
+}
+
+impl Record {
+    pub fn name(self) -> &'static str {
+        match self {
+            Record::Time => "time",
+            Record::Score => "score",
+            Record::TimeSurvived => "time-survived",
+        }
+    }
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 6] = [
+        GameMode::Marathon,
+        GameMode::Sprint,
+        GameMode::Ultra,
+        GameMode::Dig,
+        GameMode::Survival,
+        GameMode::Puzzle,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GameMode::Marathon => "Marathon",
+            GameMode::Sprint => "Sprint",
+            GameMode::Ultra => "Ultra",
+            GameMode::Dig => "Dig",
+            GameMode::Survival => "Survival",
+            GameMode::Puzzle => "Puzzle",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&m| m == self).unwrap();
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+
+    /// Lines to clear for the next level, where 0 means that the level never changes
+    pub fn rows_per_level(self) -> u8 {
+        match self {
+            GameMode::Marathon | GameMode::Survival => 10,
+            GameMode::Sprint | GameMode::Ultra | GameMode::Dig | GameMode::Puzzle => 0,
+        }
+    }
+
+    /// The game is won when this many lines have been cleared
+    pub fn line_goal(self) -> Option<u32> {
+        match self {
+            GameMode::Sprint => Some(40),
+            _ => None,
+        }
+    }
+
+    /// Whether the board starts with garbage rows, and the game is won when they are cleared
+    pub fn starts_with_garbage(self) -> bool {
+        self == GameMode::Dig
+    }
+
+    /// How often a garbage row rises from the bottom, at this level
+    pub fn garbage_interval(self, level: u8) -> Option<TimeDelta> {
+        match self {
+            GameMode::Survival => {
+                let ms = (8000.0 * 0.85f64.powi(level as i32)).max(1000.0);
+                Some(TimeDelta::milliseconds(ms as i64))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether there are garbage rows with holes in them, at the start or later
+    pub fn has_garbage(self) -> bool {
+        self.starts_with_garbage() || self.garbage_interval(0).is_some()
+    }
+
+    /// Whether the game is meant to end by topping out, so that it counts as finished
+    pub fn ends_at_top_out(self) -> bool {
+        self == GameMode::Survival
+    }
+
+    /// Whether the game ends when the time limit in the rules has passed
+    pub fn has_time_limit(self) -> bool {
+        self == GameMode::Ultra
+    }
+
+    /// The time is recorded every time this many more lines have been cleared
+    pub fn split_lines(self) -> Option<u32> {
+        match self {
+            GameMode::Sprint => Some(10),
+            _ => None,
+        }
+    }
+
+    /// Whether the game can go on after it has been won
+    pub fn has_post_game(self) -> bool {
+        self == GameMode::Marathon
+    }
+
+    /// What is remembered as the best result in this mode
+    pub fn record(self) -> Option<Record> {
+        match self {
+            GameMode::Marathon | GameMode::Puzzle => None,
+            GameMode::Sprint | GameMode::Dig => Some(Record::Time),
+            GameMode::Ultra => Some(Record::Score),
+            GameMode::Survival => Some(Record::TimeSurvived),
+        }
+    }
+}
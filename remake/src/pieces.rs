@@ -0,0 +1,156 @@
+//! Piece sets, loaded from a simple text format.
+//!
+//! A file starts with an optional `name: ...` line, followed by the pieces separated by blank
+//! lines. Each piece is a header line with its letter, and optionally a kick table (`none`,
+//! `jlstz` or `i`, default `jlstz`) and a color (`#rrggbb`). Then comes a square grid where `#` is
+//! a cell and `.` is empty, showing the piece in its spawn orientation inside the square it rotates
+//! in. Lines starting with `//` are ignored.
+
+use anyhow::{Context, bail, ensure};
+
+use crate::{
+    game::{Pos, Shape},
+    srs::KickTable,
+};
+
+static BUILTIN_SETS: [&str; 3] = [
+    include_str!("assets/pieces/tetrominoes.txt"),
+    include_str!("assets/pieces/trominoes.txt"),
+    include_str!("assets/pieces/pentominoes.txt"),
+];
+
+/// The largest square a piece can rotate in
+const MAX_SIZE: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct PieceDef {
+    pub letter: char,
+    pub shape: Shape,
+    /// Pieces without a color use a texture if there is one for the letter
+    pub color: Option<[u8; 3]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PieceSet {
+    pub name: String,
+    pub pieces: Vec<PieceDef>,
+    /// A hash of the pieces in the file, which tells sets with the same name apart
+    pub fingerprint: u32,
+}
+
+impl PieceSet {
+    /// The sets that come with the game, starting with the standard one
+    pub fn builtin() -> Vec<PieceSet> {
+        BUILTIN_SETS
+            .iter()
+            .map(|text| PieceSet::parse(text).expect("built-in piece sets are valid"))
+            .collect()
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<PieceSet> {
+        let mut name = String::from("Custom");
+        let mut blocks: Vec<Vec<&str>> = vec![Vec::new()];
+
+        for line in text.lines().map(str::trim) {
+            if line.starts_with("//") {
+                continue;
+            }
+            if let Some(n) = line.strip_prefix("name:") {
+                name = n.trim().to_string();
+            } else if line.is_empty() {
+                blocks.push(Vec::new());
+            } else {
+                blocks.last_mut().unwrap().push(line);
+            }
+        }
+
+        // FNV-1a, which unlike the standard library's hasher stays the same between versions
+        let mut fingerprint: u32 = 0x811c9dc5;
+        for line in blocks.iter().flatten() {
+            for byte in line.bytes().chain([b'\n']) {
+                fingerprint = (fingerprint ^ byte as u32).wrapping_mul(0x01000193);
+            }
+        }
+
+        let mut pieces: Vec<PieceDef> = Vec::new();
+        for block in blocks.into_iter().filter(|b| !b.is_empty()) {
+            let piece =
+                parse_piece(&block).with_context(|| format!("parsing piece '{}'", block[0]))?;
+            ensure!(
+                pieces.iter().all(|p| p.letter != piece.letter),
+                "the letter {} is used more than once",
+                piece.letter
+            );
+            pieces.push(piece);
+        }
+        ensure!(!pieces.is_empty(), "the piece set has no pieces");
+
+        Ok(PieceSet {
+            name,
+            pieces,
+            fingerprint,
+        })
+    }
+
+    pub fn letters(&self) -> Vec<char> {
+        let mut letters: Vec<char> = self.pieces.iter().map(|p| p.letter).collect();
+        letters.sort();
+        letters
+    }
+}
+
+fn parse_piece(lines: &[&str]) -> anyhow::Result<PieceDef> {
+    let mut header = lines[0].split_whitespace();
+    let letter = header.next().unwrap();
+    let mut chars = letter.chars();
+    let (Some(letter), None) = (chars.next(), chars.next()) else {
+        bail!("the letter must be a single character");
+    };
+    ensure!(
+        letter.is_alphanumeric(),
+        "the letter must be a letter or a digit"
+    );
+
+    let mut kicks = KickTable::Jlstz;
+    let mut color = None;
+    for word in header {
+        if let Some(hex) = word.strip_prefix('#') {
+            ensure!(hex.len() == 6, "colors are written as #rrggbb");
+            let value = u32::from_str_radix(hex, 16).context("invalid color")?;
+            color = Some([(value >> 16) as u8, (value >> 8) as u8, value as u8]);
+        } else {
+            kicks = match word {
+                "none" => KickTable::None,
+                "jlstz" => KickTable::Jlstz,
+                "i" => KickTable::I,
+                _ => bail!("unknown kick table: {word}"),
+            };
+        }
+    }
+
+    let grid = &lines[1..];
+    let size = grid.len();
+    ensure!(
+        size > 0 && size <= MAX_SIZE,
+        "the grid must have 1 to {MAX_SIZE} rows"
+    );
+
+    let mut offsets = Vec::new();
+    for (y, row) in grid.iter().enumerate() {
+        ensure!(row.chars().count() == size, "the grid must be square");
+        for (x, c) in row.chars().enumerate() {
+            match c {
+                '#' => offsets.push(Pos::new(x as i8, y as i8)),
+                '.' => {}
+                _ => bail!("unexpected character in the grid: {c}"),
+            }
+        }
+    }
+    ensure!(!offsets.is_empty(), "the piece has no cells");
+
+    Ok(PieceDef {
+        letter,
+        shape: Shape::new(offsets, size as i8, kicks),
+        color,
+    })
+}
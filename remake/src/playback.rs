@@ -0,0 +1,317 @@
+//! Watching replays, with pause, speed control, frame stepping and seeking.
+//!
+//! Seeking backwards starts over from a snapshot of the game taken every few seconds of game time,
+//! and plays the replay forward from there to the point that was asked for.
+
+use std::rc::Rc;
+
+use chrono::TimeDelta;
+use winit::keyboard::KeyCode;
+
+use crate::{
+    game::Game,
+    input::Action,
+    pieces::PieceSet,
+    replay::{Replay, ReplayEvent},
+};
+
+/// The speeds that playback can run at, from slowest to fastest
+pub const SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+/// How much game time passes between the snapshots that seeking starts from
+const SNAPSHOT_INTERVAL_SECS: i64 = 5;
+
+/// How far the left and right keys seek
+const SEEK_SECS: i64 = 5;
+
+/// The game as it was at some point in the replay, so that seeking doesn't have to start over
+struct Checkpoint {
+    /// The index of the next event to apply
+    position: usize,
+    time: TimeDelta,
+    held: [bool; Action::ALL.len()],
+    snapshot: String,
+}
+
+/// Plays a replay back in place of the keyboard
+pub struct Playback {
+    replay: Replay,
+    piece_sets: Rc<[PieceSet]>,
+    pub game: Game,
+    /// The index of the next event to apply
+    position: usize,
+    /// How much game time has been played back
+    pub time: TimeDelta,
+    /// The length of the whole replay
+    pub duration: TimeDelta,
+    /// Time that has passed but has not been played back yet, because the next update is longer
+    pending: TimeDelta,
+    /// Index into `SPEEDS`
+    speed: usize,
+    pub paused: bool,
+    /// Which actions are held down at this point in the replay, in the order of `Action::ALL`
+    pub held: [bool; Action::ALL.len()],
+    checkpoints: Vec<Checkpoint>,
+}
+
+/// Something the player chose to do that the app has to take care of
+pub enum PlaybackRequest {
+    Exit,
+}
+
+impl Playback {
+    pub fn new(replay: Replay, piece_sets: Rc<[PieceSet]>) -> anyhow::Result<Self> {
+        let game = replay.start_game(piece_sets.clone())?;
+        let duration = replay
+            .events
+            .iter()
+            .map(|event| match event {
+                ReplayEvent::Update(time_passed) => *time_passed,
+                _ => TimeDelta::zero(),
+            })
+            .sum();
+
+        Ok(Self {
+            replay,
+            piece_sets,
+            game,
+            position: 0,
+            time: TimeDelta::zero(),
+            duration,
+            pending: TimeDelta::zero(),
+            speed: SPEEDS.iter().position(|&s| s == 1.0).unwrap(),
+            paused: false,
+            held: [false; Action::ALL.len()],
+            checkpoints: Vec::new(),
+        })
+    }
+
+    pub fn speed(&self) -> f64 {
+        SPEEDS[self.speed]
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.replay.events.len()
+    }
+
+    pub fn handle_key(&mut self, code: KeyCode, is_pressed: bool) -> Option<PlaybackRequest> {
+        if !is_pressed {
+            return None;
+        }
+        match code {
+            KeyCode::Escape => return Some(PlaybackRequest::Exit),
+            KeyCode::Space | KeyCode::KeyP => {
+                if self.is_finished() {
+                    self.seek(TimeDelta::zero());
+                    self.paused = false;
+                } else {
+                    self.paused = !self.paused;
+                }
+            }
+            KeyCode::ArrowUp => self.speed = (self.speed + 1).min(SPEEDS.len() - 1),
+            KeyCode::ArrowDown => self.speed = self.speed.saturating_sub(1),
+            KeyCode::ArrowLeft => self.seek(self.time - TimeDelta::seconds(SEEK_SECS)),
+            KeyCode::ArrowRight => self.seek(self.time + TimeDelta::seconds(SEEK_SECS)),
+            KeyCode::Home => self.seek(TimeDelta::zero()),
+            KeyCode::Period => {
+                self.paused = true;
+                self.step_frame();
+            }
+            KeyCode::Comma => {
+                self.paused = true;
+                if let Some(last_frame) = self.last_frame_time() {
+                    self.seek(self.time - last_frame);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Plays back as much of the replay as fits in the time that has passed
+    pub fn update(&mut self, time_passed: TimeDelta) {
+        if self.paused || self.is_finished() {
+            return;
+        }
+        let scaled = time_passed.as_seconds_f64() * self.speed();
+        self.pending += TimeDelta::nanoseconds((scaled * 1e9) as i64);
+
+        while let Some(&event) = self.replay.events.get(self.position) {
+            if let ReplayEvent::Update(frame) = event {
+                if frame > self.pending {
+                    break;
+                }
+                self.pending -= frame;
+            }
+            self.apply_next();
+        }
+        if self.is_finished() {
+            self.pending = TimeDelta::zero();
+        }
+    }
+
+    /// Plays back everything up to and including the next update
+    fn step_frame(&mut self) {
+        while let Some(&event) = self.replay.events.get(self.position) {
+            self.apply_next();
+            if let ReplayEvent::Update(_) = event {
+                break;
+            }
+        }
+    }
+
+    /// The length of the last update that was played back
+    fn last_frame_time(&self) -> Option<TimeDelta> {
+        self.replay.events[..self.position]
+            .iter()
+            .rev()
+            .find_map(|event| match event {
+                ReplayEvent::Update(time_passed) => Some(*time_passed),
+                _ => None,
+            })
+    }
+
+    /// Jumps to a point in the replay by simulating from the last checkpoint before it
+    pub fn seek(&mut self, target: TimeDelta) {
+        let target = target.clamp(TimeDelta::zero(), self.duration);
+        if target < self.time || self.next_checkpoint_before(target) {
+            self.restore_checkpoint(target);
+        }
+
+        while let Some(&event) = self.replay.events.get(self.position) {
+            if let ReplayEvent::Update(frame) = event
+                && self.time + frame > target
+            {
+                break;
+            }
+            self.apply_next();
+        }
+        self.pending = TimeDelta::zero();
+    }
+
+    /// Whether a checkpoint after the current time can be used to get closer to the target
+    fn next_checkpoint_before(&self, target: TimeDelta) -> bool {
+        self.checkpoints
+            .iter()
+            .any(|c| c.time > self.time && c.time <= target)
+    }
+
+    fn restore_checkpoint(&mut self, target: TimeDelta) {
+        let checkpoint = self.checkpoints.iter().rev().find(|c| c.time <= target);
+        let restored = checkpoint.and_then(|c| {
+            match Game::from_checkpoint(&c.snapshot, self.piece_sets.clone()) {
+                Ok(game) => Some((c, game)),
+                Err(err) => {
+                    log::warn!("Failed to restore a replay checkpoint: {:#}", err);
+                    None
+                }
+            }
+        });
+
+        match restored {
+            Some((checkpoint, game)) => {
+                self.position = checkpoint.position;
+                self.time = checkpoint.time;
+                self.held = checkpoint.held;
+                self.game = game;
+            }
+            None => {
+                self.game = self
+                    .replay
+                    .start_game(self.piece_sets.clone())
+                    .expect("the replay has started before");
+                self.position = 0;
+                self.time = TimeDelta::zero();
+                self.held = [false; Action::ALL.len()];
+            }
+        }
+    }
+
+    fn apply_next(&mut self) {
+        let event = self.replay.events[self.position];
+        self.game.apply_replay_event(event);
+        self.position += 1;
+
+        let action_index = |action| Action::ALL.iter().position(|&a| a == action).unwrap();
+        match event {
+            ReplayEvent::Update(time_passed) => {
+                self.time += time_passed;
+                self.add_checkpoint();
+            }
+            ReplayEvent::Press(action) => self.held[action_index(action)] = true,
+            ReplayEvent::Release(action) => self.held[action_index(action)] = false,
+            ReplayEvent::ReleaseAll => self.held = [false; Action::ALL.len()],
+            ReplayEvent::KeepPlaying => {}
+        }
+    }
+
+    fn add_checkpoint(&mut self) {
+        let last_time = self
+            .checkpoints
+            .last()
+            .map_or(TimeDelta::zero(), |c| c.time);
+        if self.time - last_time < TimeDelta::seconds(SNAPSHOT_INTERVAL_SECS)
+            || !self.game.in_progress()
+        {
+            return;
+        }
+        self.checkpoints.push(Checkpoint {
+            position: self.position,
+            time: self.time,
+            held: self.held,
+            snapshot: self.game.to_snapshot(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Rules;
+
+    /// A game of 25 seconds where keys are held across checkpoints. It stops before the game ends,
+    /// so that nothing is written to storage.
+    fn record_game(piece_sets: Rc<[PieceSet]>) -> Replay {
+        let mut game = Game::new(Rules::default(), 42, piece_sets);
+        game.start();
+        let frame = TimeDelta::nanoseconds(16_666_667);
+        for i in 0..1500 {
+            match i % 240 {
+                0 => game.handle_action(Action::SoftDrop, true),
+                40 => game.handle_action(Action::MoveLeft, true),
+                100 => game.handle_action(Action::MoveLeft, false),
+                130 => game.handle_action(Action::RotateCw, true),
+                131 => game.handle_action(Action::RotateCw, false),
+                150 => game.handle_action(Action::MoveRight, true),
+                230 => game.handle_action(Action::MoveRight, false),
+                _ => {}
+            }
+            game.update(frame);
+        }
+        assert!(game.in_progress());
+        game.replay.clone().unwrap()
+    }
+
+    #[test]
+    fn seeking_gives_the_same_game_as_playing_straight_through() {
+        let piece_sets: Rc<[PieceSet]> = PieceSet::builtin().into();
+        let replay = record_game(piece_sets.clone());
+        let expected = replay.play(piece_sets.clone()).unwrap();
+
+        let mut playback = Playback::new(replay, piece_sets).unwrap();
+        let duration = playback.duration;
+        for target in [duration, TimeDelta::seconds(7), duration] {
+            playback.seek(target);
+        }
+        assert_eq!(playback.game.summary(), expected.summary());
+
+        for target in [12, 3, 24, 18, 22] {
+            playback.seek(TimeDelta::seconds(target));
+            playback.handle_key(KeyCode::Comma, true);
+            playback.handle_key(KeyCode::Period, true);
+        }
+        playback.seek(duration);
+        assert_eq!(playback.game.summary(), expected.summary());
+        assert_eq!(playback.game.to_snapshot(), expected.to_snapshot());
+    }
+}
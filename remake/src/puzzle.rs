@@ -0,0 +1,193 @@
+//! Puzzles, loaded from a simple text format.
+//!
+//! Each puzzle starts with a `name: ...` line, followed by a `goal:` line (`lines <count>`,
+//! `perfect clear` or `t-spin double`), a `queue:` line with the letters of the pieces in order,
+//! and a `board:` line. The rows after `board:` are placed at the bottom of the board, where `.` is
+//! empty, `#` is garbage and a letter is a tile of that piece. Lines starting with `//` are ignored.
+
+use std::sync::LazyLock;
+
+use anyhow::{Context, bail, ensure};
+use chrono::TimeDelta;
+
+use crate::{
+    game::{Board, GARBAGE_TILE, HIDDEN_ROWS, Pos},
+    rules::{BOARD_HEIGHTS, BOARD_WIDTHS},
+};
+
+static BUILTIN_PUZZLES: LazyLock<Vec<Puzzle>> = LazyLock::new(|| {
+    Puzzle::parse_all(include_str!("assets/puzzles.txt")).expect("built-in puzzles are valid")
+});
+
+/// What has to be done before the pieces run out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PuzzleGoal {
+    ClearLines(u32),
+    /// Clear every tile on the board
+    PerfectClear,
+    TSpinDouble,
+}
+
+impl PuzzleGoal {
+    pub fn description(self) -> String {
+        match self {
+            PuzzleGoal::ClearLines(1) => "Clear 1 line".to_string(),
+            PuzzleGoal::ClearLines(count) => format!("Clear {count} lines"),
+            PuzzleGoal::PerfectClear => "Perfect clear".to_string(),
+            PuzzleGoal::TSpinDouble => "T-Spin Double".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Puzzle {
+    pub name: String,
+    pub goal: PuzzleGoal,
+    pub queue: Vec<char>,
+    /// The bottom rows of the board, from the top
+    rows: Vec<Vec<char>>,
+}
+
+impl Puzzle {
+    /// The puzzles that come with the game, which use the standard piece set
+    pub fn builtin() -> &'static [Puzzle] {
+        &BUILTIN_PUZZLES
+    }
+
+    pub fn parse_all(text: &str) -> anyhow::Result<Vec<Puzzle>> {
+        let mut blocks: Vec<Vec<&str>> = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            if line.starts_with("name:") || blocks.is_empty() {
+                blocks.push(Vec::new());
+            }
+            blocks.last_mut().unwrap().push(line);
+        }
+
+        blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| {
+                parse_puzzle(block).with_context(|| format!("parsing puzzle {}", i + 1))
+            })
+            .collect()
+    }
+
+    /// The starting board, as wide as the puzzle and at least `height` rows high
+    pub fn board(&self, height: u8) -> Board {
+        let width = self.rows[0].len() as u8;
+        let height = height.max(self.rows.len() as u8 + 1);
+        let mut board = Board::new(width, height, HIDDEN_ROWS);
+
+        let top = height as usize - self.rows.len();
+        for (i, row) in self.rows.iter().enumerate() {
+            for (x, &tile) in row.iter().enumerate() {
+                if tile != '.' {
+                    let pos = Pos::new(x as i8, (top + i) as i8);
+                    board.set_tile(pos, tile, TimeDelta::zero());
+                }
+            }
+        }
+        board
+    }
+}
+
+fn parse_puzzle(lines: &[&str]) -> anyhow::Result<Puzzle> {
+    let mut name = None;
+    let mut goal = None;
+    let mut queue = None;
+    let mut rows: Vec<Vec<char>> = Vec::new();
+    let mut in_board = false;
+
+    for &line in lines {
+        if in_board {
+            let row: Vec<char> = line.chars().collect();
+            for &c in &row {
+                ensure!(
+                    c == '.' || c == GARBAGE_TILE || c.is_alphanumeric(),
+                    "unexpected character in the board: {c}"
+                );
+            }
+            rows.push(row);
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once(':') else {
+            bail!("expected a `key: value` line, found: {line}");
+        };
+        let value = value.trim();
+        match key.trim() {
+            "name" => name = Some(value.to_string()),
+            "goal" => goal = Some(parse_goal(value)?),
+            "queue" => queue = Some(value.chars().filter(|c| !c.is_whitespace()).collect()),
+            "board" => in_board = true,
+            key => bail!("unknown key: {key}"),
+        }
+    }
+
+    let queue: Vec<char> = queue.context("missing queue")?;
+    ensure!(!queue.is_empty(), "the queue is empty");
+    ensure!(!rows.is_empty(), "the board is empty");
+    ensure!(
+        rows.len() < *BOARD_HEIGHTS.end() as usize,
+        "the board can have at most {} rows",
+        BOARD_HEIGHTS.end() - 1
+    );
+    let width = rows[0].len();
+    ensure!(
+        BOARD_WIDTHS.contains(&width.try_into().unwrap_or(u8::MAX)),
+        "the board must be {BOARD_WIDTHS:?} columns wide"
+    );
+    ensure!(
+        rows.iter().all(|row| row.len() == width),
+        "every row of the board must be as wide"
+    );
+
+    Ok(Puzzle {
+        name: name.context("missing name")?,
+        goal: goal.context("missing goal")?,
+        queue,
+        rows,
+    })
+}
+
+fn parse_goal(text: &str) -> anyhow::Result<PuzzleGoal> {
+    Ok(match text {
+        "perfect clear" => PuzzleGoal::PerfectClear,
+        "t-spin double" => PuzzleGoal::TSpinDouble,
+        _ => match text.strip_prefix("lines") {
+            Some(count) => {
+                PuzzleGoal::ClearLines(count.trim().parse().context("parsing line count")?)
+            }
+            None => bail!("unknown goal: {text}"),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pieces::PieceSet;
+
+    #[test]
+    fn builtin_puzzles_use_the_standard_pieces() {
+        let letters = PieceSet::builtin()[0].letters();
+        assert!(!Puzzle::builtin().is_empty());
+        for puzzle in Puzzle::builtin() {
+            let tiles = puzzle
+                .rows
+                .iter()
+                .flatten()
+                .filter(|&&c| c != '.' && c != GARBAGE_TILE);
+            for letter in puzzle.queue.iter().chain(tiles) {
+                assert!(
+                    letters.contains(letter),
+                    "{}: unknown piece {letter}",
+                    puzzle.name
+                );
+            }
+        }
+    }
+}
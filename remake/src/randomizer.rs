@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+
+use anyhow::ensure;
+use rand::{Rng, RngCore, seq::SliceRandom};
+
+/// Decides the order in which pieces are handed out
+pub trait Randomizer {
+    fn next_shape(&mut self, rng: &mut dyn RngCore) -> char;
+
+    /// What the randomizer remembers between pieces, so that a saved game can continue
+    fn state(&self) -> String;
+
+    fn restore(&mut self, state: &str) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum RandomizerKind {
+    /// Every piece once in a random order, then repeat
+    #[default]
+    SevenBag,
+    /// Like the 7-bag, but every piece twice
+    FourteenBag,
+    /// Avoids the last four pieces by trying up to four times (like in TGM)
+    Tgm,
+    /// Tries again once if the piece is the same as the last one (like on the NES)
+    Nes,
+    /// Every piece is equally likely every time
+    Random,
+}
+
+impl RandomizerKind {
+    pub const ALL: [RandomizerKind; 5] = [
+        RandomizerKind::SevenBag,
+        RandomizerKind::FourteenBag,
+        RandomizerKind::Tgm,
+        RandomizerKind::Nes,
+        RandomizerKind::Random,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RandomizerKind::SevenBag => "7-bag",
+            RandomizerKind::FourteenBag => "14-bag",
+            RandomizerKind::Tgm => "TGM",
+            RandomizerKind::Nes => "NES",
+            RandomizerKind::Random => "Random",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&k| k == self).unwrap();
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+
+    pub fn create(self, pieces: &[char]) -> Box<dyn Randomizer> {
+        let pieces = pieces.to_vec();
+        match self {
+            RandomizerKind::SevenBag => Box::new(BagRandomizer::new(pieces, 1)),
+            RandomizerKind::FourteenBag => Box::new(BagRandomizer::new(pieces, 2)),
+            RandomizerKind::Tgm => Box::new(HistoryRandomizer::new(pieces, 4, 4)),
+            RandomizerKind::Nes => Box::new(NesRandomizer::new(pieces)),
+            RandomizerKind::Random => Box::new(PureRandomizer::new(pieces)),
+        }
+    }
+}
+
+pub struct BagRandomizer {
+    pieces: Vec<char>,
+    copies: usize,
+    bag: Vec<char>,
+}
+
+impl BagRandomizer {
+    pub fn new(pieces: Vec<char>, copies: usize) -> Self {
+        Self {
+            pieces,
+            copies,
+            bag: Vec::new(),
+        }
+    }
+}
+
+impl Randomizer for BagRandomizer {
+    fn next_shape(&mut self, rng: &mut dyn RngCore) -> char {
+        if self.bag.is_empty() {
+            for _ in 0..self.copies {
+                self.bag.extend_from_slice(&self.pieces);
+            }
+            self.bag.shuffle(rng);
+        }
+        self.bag.pop().unwrap()
+    }
+
+    fn state(&self) -> String {
+        self.bag.iter().collect()
+    }
+
+    fn restore(&mut self, state: &str) -> anyhow::Result<()> {
+        let bag: Vec<char> = state.chars().collect();
+        ensure!(
+            bag.iter().all(|c| self.pieces.contains(c)),
+            "the bag has unknown pieces"
+        );
+        self.bag = bag;
+        Ok(())
+    }
+}
+
+pub struct HistoryRandomizer {
+    pieces: Vec<char>,
+    history: VecDeque<char>,
+    /// How many pieces are drawn at most, where the last one is kept even if it is in the history
+    tries: u32,
+}
+
+impl HistoryRandomizer {
+    pub fn new(pieces: Vec<char>, history_len: usize, tries: u32) -> Self {
+        // TGM starts with S and Z in the history so that the first piece is never an overhang
+        let mut history: VecDeque<char> = "ZSSZ"
+            .chars()
+            .filter(|c| pieces.contains(c))
+            .cycle()
+            .take(history_len)
+            .collect();
+        history.resize(history_len, ' ');
+
+        Self {
+            pieces,
+            history,
+            tries,
+        }
+    }
+}
+
+impl Randomizer for HistoryRandomizer {
+    fn next_shape(&mut self, rng: &mut dyn RngCore) -> char {
+        let mut letter = self.pieces[rng.random_range(0..self.pieces.len())];
+        for _ in 1..self.tries {
+            if !self.history.contains(&letter) {
+                break;
+            }
+            letter = self.pieces[rng.random_range(0..self.pieces.len())];
+        }
+
+        self.history.pop_front();
+        self.history.push_back(letter);
+        letter
+    }
+
+    fn state(&self) -> String {
+        self.history.iter().collect()
+    }
+
+    fn restore(&mut self, state: &str) -> anyhow::Result<()> {
+        let history: VecDeque<char> = state.chars().collect();
+        ensure!(
+            history.len() == self.history.len(),
+            "the history has the wrong length"
+        );
+        self.history = history;
+        Ok(())
+    }
+}
+
+pub struct NesRandomizer {
+    pieces: Vec<char>,
+    last: Option<char>,
+}
+
+impl NesRandomizer {
+    pub fn new(pieces: Vec<char>) -> Self {
+        Self { pieces, last: None }
+    }
+}
+
+impl Randomizer for NesRandomizer {
+    fn next_shape(&mut self, rng: &mut dyn RngCore) -> char {
+        // The NES rolls one extra value that, just like a repeated piece, causes a reroll
+        let idx = rng.random_range(0..=self.pieces.len());
+        let letter = match self.pieces.get(idx) {
+            Some(&letter) if Some(letter) != self.last => letter,
+            _ => self.pieces[rng.random_range(0..self.pieces.len())],
+        };
+
+        self.last = Some(letter);
+        letter
+    }
+
+    fn state(&self) -> String {
+        self.last.map(String::from).unwrap_or_default()
+    }
+
+    fn restore(&mut self, state: &str) -> anyhow::Result<()> {
+        self.last = state.chars().next();
+        Ok(())
+    }
+}
+
+pub struct PureRandomizer {
+    pieces: Vec<char>,
+}
+
+impl PureRandomizer {
+    pub fn new(pieces: Vec<char>) -> Self {
+        Self { pieces }
+    }
+}
+
+impl Randomizer for PureRandomizer {
+    fn next_shape(&mut self, rng: &mut dyn RngCore) -> char {
+        self.pieces[rng.random_range(0..self.pieces.len())]
+    }
+
+    fn state(&self) -> String {
+        String::new()
+    }
+
+    fn restore(&mut self, _state: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
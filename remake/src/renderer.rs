@@ -1,18 +1,24 @@
 use std::collections::HashMap;
 
 use anyhow::Context;
-use wgpu::util::DeviceExt;
 use wgpu_text::{
     BrushBuilder, TextBrush,
     glyph_brush::{
-        FontId, HorizontalAlign, Layout, Section as TextSection, Text, ab_glyph::FontRef,
+        FontId, HorizontalAlign, Layout, OwnedSection, Section as TextSection, Text,
+        ab_glyph::FontRef,
     },
 };
 
 use crate::{
     canvas::Canvas,
-    game::{Game, GameState, Pos},
+    game::{Board, GARBAGE_TILE, Game, GameState, Pos, Shape},
+    input::Action,
+    mode::Record,
+    pieces::PieceSet,
+    playback::Playback,
+    rules::MAX_PREVIEW_COUNT,
     tile::{Tile, TileRenderer, Vertex},
+    time::format_duration,
 };
 
 pub mod fonts {
@@ -21,15 +27,39 @@ pub mod fonts {
     pub const ALL_FONTS: [&[u8]; 1] = [ARIAL_ROUNDED];
 }
 
+static PIECE_TEXTURES: &[(char, &[u8])] = &[
+    ('I', include_bytes!("assets/I.png")),
+    ('J', include_bytes!("assets/J.png")),
+    ('L', include_bytes!("assets/L.png")),
+    ('O', include_bytes!("assets/O.png")),
+    ('S', include_bytes!("assets/S.png")),
+    ('T', include_bytes!("assets/T.png")),
+    ('Z', include_bytes!("assets/Z.png")),
+];
+
 pub struct Renderer {
     fonts: HashMap<&'static [u8], FontId>,
     text_brush: TextBrush<FontRef<'static>>,
     tile_renderer: TileRenderer,
     piece_vertex_buffer: wgpu::Buffer,
     piece_texture_bind_groups: HashMap<char, wgpu::BindGroup>,
+    /// The pieces and colors the textures were made for, so they can be replaced when it changes
+    piece_textures_for: Vec<(char, Option<[u8; 3]>)>,
+    ghost_bind_group: wgpu::BindGroup,
+    garbage_bind_group: wgpu::BindGroup,
+    board_background_bind_group: wgpu::BindGroup,
     scale_factor: f32,
 }
 
+fn create_vertex_buffer(device: &wgpu::Device, max_tiles: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Vertex Buffer"),
+        size: Vertex::desc().array_stride * 6 * max_tiles as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
 impl Renderer {
     pub fn new(
         device: &wgpu::Device,
@@ -53,42 +83,33 @@ impl Renderer {
 
         let tile_renderer = TileRenderer::new(device, config.format);
 
-        let mut piece_texture_bind_groups = HashMap::new();
-
-        static ALL_PIECES: &[(char, &[u8])] = &[
-            ('I', include_bytes!("assets/I.png")),
-            ('J', include_bytes!("assets/J.png")),
-            ('L', include_bytes!("assets/L.png")),
-            ('O', include_bytes!("assets/O.png")),
-            ('S', include_bytes!("assets/S.png")),
-            ('T', include_bytes!("assets/T.png")),
-            ('Z', include_bytes!("assets/Z.png")),
-        ];
-
-        for &(ch, texture_bytes) in ALL_PIECES {
-            let tex = crate::texture::Texture::from_bytes(device, queue, texture_bytes, "piece")
-                .context("creating piece texture")?;
-
-            piece_texture_bind_groups.insert(ch, tile_renderer.create_bind_group(device, &tex));
-        }
-
         let tex =
             crate::texture::Texture::from_color(device, queue, [0, 0, 80, 200], "ghost_piece")
                 .context("creating ghost piece texture")?;
-        piece_texture_bind_groups.insert('G', tile_renderer.create_bind_group(device, &tex));
+        let ghost_bind_group = tile_renderer.create_bind_group(device, &tex);
+
+        let tex =
+            crate::texture::Texture::from_color(device, queue, [110, 110, 110, 255], "garbage")
+                .context("creating garbage texture")?;
+        let garbage_bind_group = tile_renderer.create_bind_group(device, &tex);
+
+        let tex = crate::texture::Texture::from_color(device, queue, [0, 0, 20, 255], "board")
+            .context("creating board background texture")?;
+        let board_background_bind_group = tile_renderer.create_bind_group(device, &tex);
 
-        let piece_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: &[0; Vertex::desc().array_stride as usize * 6 * 10 * 20],
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
+        // This will grow when the size of the board is known
+        let piece_vertex_buffer = create_vertex_buffer(device, 1);
 
         Ok(Self {
             fonts,
             text_brush,
             tile_renderer,
             piece_vertex_buffer,
-            piece_texture_bind_groups,
+            piece_texture_bind_groups: HashMap::new(),
+            piece_textures_for: Vec::new(),
+            ghost_bind_group,
+            garbage_bind_group,
+            board_background_bind_group,
             scale_factor: 1.0, // will be replaced
         })
     }
@@ -102,8 +123,18 @@ impl Renderer {
         self.scale_factor = scale_factor;
     }
 
-    pub fn render(&mut self, game: &Game, canvas: &Canvas) -> Result<(), wgpu::SurfaceError> {
-        self.update_text(game, canvas);
+    /// Draws the game, with the controls and inputs of the replay if it is being played back
+    pub fn render(
+        &mut self,
+        game: &Game,
+        playback: Option<&Playback>,
+        canvas: &Canvas,
+    ) -> Result<(), wgpu::SurfaceError> {
+        self.update_text(game, playback, canvas);
+        self.reserve_tiles(&canvas.device, game);
+        if let Err(err) = self.update_piece_textures(canvas, game.piece_set()) {
+            log::error!("Failed to create piece textures: {:#}", err);
+        }
 
         let frame = canvas.surface.get_current_texture()?;
         let view = frame.texture.create_view(&Default::default());
@@ -142,11 +173,70 @@ impl Renderer {
         Ok(())
     }
 
-    fn update_text(&mut self, game: &Game, canvas: &Canvas) {
-        let text_sections = self.create_text_sections(game, canvas);
+    /// Makes sure there is room in the vertex buffer for every tile that can be drawn for this board
+    fn reserve_tiles(&mut self, device: &wgpu::Device, game: &Game) {
+        let board_tiles = game.board.width as usize * game.board.height as usize;
+        let max_cells = game
+            .shapes
+            .values()
+            .map(Shape::cell_count)
+            .max()
+            .unwrap_or(0);
+        // The background, and then the moving piece, its ghost, the held piece and the previews
+        let max_tiles = board_tiles + 1 + max_cells * (3 + MAX_PREVIEW_COUNT as usize);
+        let needed_size = Vertex::desc().array_stride * 6 * max_tiles as u64;
+        if self.piece_vertex_buffer.size() < needed_size {
+            self.piece_vertex_buffer = create_vertex_buffer(device, max_tiles);
+        }
+    }
+
+    /// Pieces with a color in the piece set get that color, then the built-in textures are used,
+    /// and any other pieces get a color from the rainbow
+    fn update_piece_textures(
+        &mut self,
+        canvas: &Canvas,
+        piece_set: &PieceSet,
+    ) -> anyhow::Result<()> {
+        let wanted: Vec<(char, Option<[u8; 3]>)> = piece_set
+            .pieces
+            .iter()
+            .map(|p| (p.letter, p.color))
+            .collect();
+        if wanted == self.piece_textures_for {
+            return Ok(());
+        }
+
+        let (device, queue) = (&canvas.device, &canvas.queue);
+        self.piece_texture_bind_groups.clear();
+        for (i, &(letter, color)) in wanted.iter().enumerate() {
+            let image = PIECE_TEXTURES.iter().find(|&&(l, _)| l == letter);
+            let tex = match (color, image) {
+                (None, Some(&(_, texture_bytes))) => {
+                    crate::texture::Texture::from_bytes(device, queue, texture_bytes, "piece")
+                }
+                _ => {
+                    let [r, g, b] =
+                        color.unwrap_or_else(|| rainbow_color(i as f32 / wanted.len() as f32));
+                    crate::texture::Texture::from_color(device, queue, [r, g, b, 255], "piece")
+                }
+            }
+            .context("creating piece texture")?;
+
+            let bind_group = self.tile_renderer.create_bind_group(device, &tex);
+            self.piece_texture_bind_groups.insert(letter, bind_group);
+        }
+        self.piece_textures_for = wanted;
+        Ok(())
+    }
+
+    fn update_text(&mut self, game: &Game, playback: Option<&Playback>, canvas: &Canvas) {
+        let mut text_sections = self.create_text_sections(game, canvas);
+        if let Some(playback) = playback {
+            text_sections.extend(self.create_playback_sections(playback, canvas));
+        }
         if let Err(err) = self
             .text_brush
-            .queue(&canvas.device, &canvas.queue, text_sections)
+            .queue(&canvas.device, &canvas.queue, &text_sections)
         {
             log::error!("Failed to update text: {}", err);
         }
@@ -156,16 +246,42 @@ impl Renderer {
         self.text_brush.draw(render_pass);
     }
 
-    fn create_text_sections(&self, game: &Game, canvas: &Canvas) -> Vec<TextSection<'static>> {
+    fn create_text_sections(&self, game: &Game, canvas: &Canvas) -> Vec<OwnedSection> {
         let mut sections = Vec::new();
 
         let cyan_color = [0, 150, 150, 200].map(|c| c as f32 / 255.0);
+        let green_color = [0, 255, 0, 255].map(|c| c as f32 / 255.0);
         let dark_red_color = [150, 0, 0, 255].map(|c| c as f32 / 255.0);
 
+        let layout = ScreenLayout::new(
+            canvas.config.width as f32,
+            canvas.config.height as f32,
+            &game.board,
+        );
+
         let big_text = match game.state {
             GameState::NotStarted => Some((("Press\nSPACE", cyan_color, 60.0), 160.0)),
+            GameState::GameOver if game.puzzle.is_some() => {
+                Some((("Failed", dark_red_color, 60.0), 260.0))
+            }
+            GameState::GameOver if game.rules.mode.ends_at_top_out() => {
+                Some((("Game Over", dark_red_color, 60.0), 160.0))
+            }
             GameState::GameOver => Some((("Game Over", dark_red_color, 60.0), 260.0)),
+            GameState::Paused if game.end_menu.is_some() => {
+                Some((("Continue?", cyan_color, 60.0), 160.0))
+            }
             GameState::Paused => Some((("Press P", cyan_color, 60.0), 160.0)),
+            GameState::Won if game.puzzle.is_some() => {
+                Some((("Solved!", green_color, 60.0), 160.0))
+            }
+            GameState::Won if game.rules.mode.has_time_limit() => {
+                Some((("Time's Up!", green_color, 60.0), 160.0))
+            }
+            GameState::Won if game.rules.mode.record().is_some() => {
+                Some((("Finished!", green_color, 60.0), 160.0))
+            }
+            GameState::Won => Some((("You Win!", green_color, 60.0), 160.0)),
             GameState::Running => None,
         };
 
@@ -179,13 +295,345 @@ impl Renderer {
                 )
                 .with_layout(Layout::default().h_align(HorizontalAlign::Center))
                 .with_screen_position((
-                    canvas.config.width as f32 / 2.0,
+                    layout.board.x + layout.board.w / 2.0,
                     y_pos * self.scale_factor,
                 ));
 
-            sections.extend(self.make_text_with_outline(main_section));
+            sections.extend(self.make_owned_text_with_outline(main_section));
         }
 
+        if game.state == GameState::Running
+            && let Some(message) = &game.clear_message
+        {
+            let purple_color = [200, 80, 255, 255].map(|c| c as f32 / 255.0);
+            let message_section = TextSection::default()
+                .add_text(
+                    Text::new(message)
+                        .with_color(purple_color)
+                        .with_scale(30.0 * self.scale_factor)
+                        .with_font_id(self.fonts[fonts::ARIAL_ROUNDED]),
+                )
+                .with_layout(Layout::default().h_align(HorizontalAlign::Center))
+                .with_screen_position((
+                    layout.board.x + layout.board.w / 2.0,
+                    layout.board.y + layout.board.h * 0.3,
+                ));
+
+            sections.extend(self.make_owned_text_with_outline(message_section));
+        }
+
+        if matches!(
+            game.state,
+            GameState::NotStarted | GameState::GameOver | GameState::Won
+        ) {
+            let seed_text = format!("Seed: {}", game.seed);
+            let seed_section = TextSection::default()
+                .add_text(
+                    Text::new(&seed_text)
+                        .with_color(cyan_color)
+                        .with_scale(20.0 * self.scale_factor)
+                        .with_font_id(self.fonts[fonts::ARIAL_ROUNDED]),
+                )
+                .with_layout(Layout::default().h_align(HorizontalAlign::Center))
+                .with_screen_position((
+                    layout.board.x + layout.board.w / 2.0,
+                    layout.board.y + layout.board.h - 40.0 * self.scale_factor,
+                ));
+
+            sections.extend(self.make_owned_text_with_outline(seed_section));
+        }
+
+        let finished = game.state == GameState::Won
+            || game.state == GameState::GameOver && game.rules.mode.ends_at_top_out();
+        if finished {
+            let best = match game.rules.mode.record() {
+                _ if game.new_best => "New best!".to_string(),
+                Some(Record::Time | Record::TimeSurvived) => game
+                    .best_time
+                    .map(|best| format!("Best: {}", format_duration(best)))
+                    .unwrap_or_default(),
+                Some(Record::Score) => game
+                    .best_score
+                    .map(|best| format!("Best: {best}"))
+                    .unwrap_or_default(),
+                None => String::new(),
+            };
+            let stats_text = match game.rules.mode.record() {
+                Some(Record::Time) => {
+                    let seconds = game.progress.time.as_seconds_f64();
+                    let pps = game.progress.pieces as f64 / seconds.max(0.001);
+                    format!(
+                        "Time: {}\nPPS: {:.2}\n{}",
+                        format_duration(game.progress.time),
+                        pps,
+                        best,
+                    )
+                }
+                Some(Record::Score) => format!(
+                    "Score: {}\nLines: {}\nPieces: {}\n{}",
+                    game.progress.score, game.progress.lines, game.progress.pieces, best,
+                ),
+                Some(Record::TimeSurvived) => format!(
+                    "Survived: {}\nLines: {}\n{}",
+                    format_duration(game.progress.time),
+                    game.progress.lines,
+                    best,
+                ),
+                None => format!(
+                    "Score: {}\nLines: {}\nTime: {}\nPieces: {}",
+                    game.progress.score,
+                    game.progress.lines,
+                    format_duration(game.progress.time),
+                    game.progress.pieces,
+                ),
+            };
+            let stats_section = TextSection::default()
+                .add_text(
+                    Text::new(&stats_text)
+                        .with_color(green_color)
+                        .with_scale(24.0 * self.scale_factor)
+                        .with_font_id(self.fonts[fonts::ARIAL_ROUNDED]),
+                )
+                .with_layout(Layout::default().h_align(HorizontalAlign::Center))
+                .with_screen_position((
+                    layout.board.x + layout.board.w / 2.0,
+                    260.0 * self.scale_factor,
+                ));
+
+            sections.extend(self.make_owned_text_with_outline(stats_section));
+        }
+
+        if let Some(menu) = &game.end_menu {
+            let yellow_color = [255, 220, 0, 255].map(|c| c as f32 / 255.0);
+            let items: Vec<(String, bool)> = menu
+                .items()
+                .map(|(text, selected)| {
+                    if selected {
+                        (format!("> {text} <\n"), true)
+                    } else {
+                        (format!("{text}\n"), false)
+                    }
+                })
+                .collect();
+
+            let mut menu_section = TextSection::default()
+                .with_layout(Layout::default().h_align(HorizontalAlign::Center))
+                .with_screen_position((
+                    layout.board.x + layout.board.w / 2.0,
+                    layout.board.y + layout.board.h * 0.65,
+                ));
+            for (text, selected) in &items {
+                let color = if *selected { yellow_color } else { cyan_color };
+                menu_section = menu_section.add_text(
+                    Text::new(text)
+                        .with_color(color)
+                        .with_scale(26.0 * self.scale_factor)
+                        .with_font_id(self.fonts[fonts::ARIAL_ROUNDED]),
+                );
+            }
+
+            sections.extend(self.make_owned_text_with_outline(menu_section));
+        }
+
+        let hold_label = TextSection::default()
+            .add_text(
+                Text::new("Hold")
+                    .with_color(green_color)
+                    .with_scale(20.0 * self.scale_factor)
+                    .with_font_id(self.fonts[fonts::ARIAL_ROUNDED]),
+            )
+            .with_layout(Layout::default().h_align(HorizontalAlign::Center))
+            .with_screen_position((
+                layout.hold.x + layout.hold.w / 2.0,
+                layout.hold.y - 30.0 * self.scale_factor,
+            ));
+        sections.extend(self.make_owned_text_with_outline(hold_label));
+
+        let next_label = TextSection::default()
+            .add_text(
+                Text::new("Next")
+                    .with_color(green_color)
+                    .with_scale(20.0 * self.scale_factor)
+                    .with_font_id(self.fonts[fonts::ARIAL_ROUNDED]),
+            )
+            .with_layout(Layout::default().h_align(HorizontalAlign::Center))
+            .with_screen_position((
+                layout.next_queue.x + layout.next_queue.w / 2.0,
+                layout.next_queue.y - 30.0 * self.scale_factor,
+            ));
+        sections.extend(self.make_owned_text_with_outline(next_label));
+
+        let mut stats = vec![format!("Score: {}", game.progress.score)];
+        match game.rules.mode.line_goal() {
+            Some(goal) => stats.push(format!("Lines: {}/{}", game.progress.lines, goal)),
+            None => stats.push(format!("Lines: {}", game.progress.lines)),
+        }
+        if game.rules.mode.rows_per_level() > 0 {
+            stats.push(format!("Level: {}", game.progress.level));
+        }
+        if game.rules.mode.starts_with_garbage() {
+            stats.push(format!("Garbage: {}", game.board.garbage_rows()));
+        }
+        if let Some(puzzle) = &game.puzzle {
+            let pieces_left = game.next_shapes.len()
+                + game.held_shape.is_some() as usize
+                + game.moving_piece.is_some() as usize;
+            stats.push(puzzle.goal.description());
+            stats.push(format!("Pieces left: {pieces_left}"));
+        }
+        match game.time_left() {
+            Some(time_left) => stats.push(format!("Time left: {}", format_duration(time_left))),
+            None => stats.push(format!("Time: {}", format_duration(game.progress.time))),
+        }
+        if let Some(split_lines) = game.rules.mode.split_lines() {
+            for (i, &split) in game.progress.splits.iter().enumerate() {
+                let lines = (i as u32 + 1) * split_lines;
+                stats.push(format!("{lines}: {}", format_duration(split)));
+            }
+        }
+        if game.state == GameState::NotStarted {
+            if let Some(best) = game.best_time {
+                stats.push(format!("Best: {}", format_duration(best)));
+            }
+            if let Some(best) = game.best_score {
+                stats.push(format!("Best: {best}"));
+            }
+        }
+
+        if game.state == GameState::NotStarted {
+            let rotation_180 = if game.rules.rotation_180 { "On" } else { "Off" };
+            // Puzzles always use the standard pieces
+            let pieces_key = if game.puzzle.is_none() { " (S)" } else { "" };
+            let mut settings = vec![
+                format!("Mode: {} (M)\n", game.rules.mode.name()),
+                format!("Scoring: {} (TAB)\n", game.rules.scoring.name()),
+                format!("180° turns: {rotation_180} (R)\n"),
+                format!("Pieces: {}{pieces_key}\n", game.piece_set().name),
+                format!("Randomizer: {} (B)\n", game.rules.randomizer.name()),
+                format!("Previews: {} (N)\n", game.rules.preview_count),
+                format!("Top out: {} (T)\n", game.rules.top_out.name()),
+                format!("Gravity: {} (G)\n", game.rules.gravity.name()),
+                format!("Blocks: {} (V)\n", game.rules.visibility.name()),
+                "Watch last replay (W)\n".to_string(),
+            ];
+            if let Some(puzzle) = &game.puzzle {
+                settings.push(format!("Puzzle: {} (L)\n", puzzle.name));
+            }
+            if game.rules.mode.starts_with_garbage() {
+                settings.push(format!("Cheese rows: {} (H)\n", game.rules.cheese_rows));
+            }
+            if game.rules.mode.has_garbage() {
+                settings.push(format!("Messiness: {}% (J)\n", game.rules.cheese_messiness));
+            }
+            let mut settings_section = TextSection::default()
+                .with_layout(Layout::default().h_align(HorizontalAlign::Center))
+                .with_screen_position((
+                    layout.board.x + layout.board.w / 2.0,
+                    layout.board.y + layout.board.h * 0.5,
+                ));
+            for text in &settings {
+                settings_section = settings_section.add_text(
+                    Text::new(text)
+                        .with_color(green_color)
+                        .with_scale(18.0 * self.scale_factor)
+                        .with_font_id(self.fonts[fonts::ARIAL_ROUNDED]),
+                );
+            }
+            sections.extend(self.make_owned_text_with_outline(settings_section));
+        }
+
+        for (i, text) in stats.into_iter().enumerate() {
+            let section = TextSection::default()
+                .add_text(
+                    Text::new(&text)
+                        .with_color(green_color)
+                        .with_scale(20.0 * self.scale_factor)
+                        .with_font_id(self.fonts[fonts::ARIAL_ROUNDED]),
+                )
+                .with_screen_position((
+                    layout.hold_panel.x + 10.0 * self.scale_factor,
+                    layout.hold.y + layout.hold.h + (20.0 + 30.0 * i as f32) * self.scale_factor,
+                ));
+
+            sections.extend(self.make_owned_text_with_outline(section));
+        }
+
+        sections
+    }
+
+    /// The position in the replay on top of the board, and the inputs that are held down at the
+    /// bottom of the hold panel
+    fn create_playback_sections(&self, playback: &Playback, canvas: &Canvas) -> Vec<OwnedSection> {
+        let mut sections = Vec::new();
+        let cyan_color = [0, 150, 150, 200].map(|c| c as f32 / 255.0);
+        let yellow_color = [255, 220, 0, 255].map(|c| c as f32 / 255.0);
+        let dim_color = [90, 90, 90, 255].map(|c| c as f32 / 255.0);
+
+        let layout = ScreenLayout::new(
+            canvas.config.width as f32,
+            canvas.config.height as f32,
+            &playback.game.board,
+        );
+
+        let status = if playback.is_finished() {
+            " (finished)"
+        } else if playback.paused {
+            " (paused)"
+        } else {
+            ""
+        };
+        let status_text = format!(
+            "Replay {}× {}/{}{status}\n",
+            playback.speed(),
+            format_duration(playback.time),
+            format_duration(playback.duration),
+        );
+        let status_section = TextSection::default()
+            .add_text(
+                Text::new(&status_text)
+                    .with_color(yellow_color)
+                    .with_scale(20.0 * self.scale_factor)
+                    .with_font_id(self.fonts[fonts::ARIAL_ROUNDED]),
+            )
+            .add_text(
+                Text::new("Space pause, Up/Down speed, Left/Right seek, ,/. step, Esc exit")
+                    .with_color(cyan_color)
+                    .with_scale(14.0 * self.scale_factor)
+                    .with_font_id(self.fonts[fonts::ARIAL_ROUNDED]),
+            )
+            .with_layout(Layout::default().h_align(HorizontalAlign::Center))
+            .with_screen_position((
+                layout.board.x + layout.board.w / 2.0,
+                layout.board.y + 10.0 * self.scale_factor,
+            ));
+        sections.extend(self.make_owned_text_with_outline(status_section));
+
+        // Two actions on each line, so that it fits in the panel
+        let labels: Vec<String> = Action::ALL
+            .iter()
+            .enumerate()
+            .map(|(i, action)| {
+                let end = if i % 2 == 1 { "\n" } else { "  " };
+                format!("{}{end}", action.label())
+            })
+            .collect();
+        let mut input_section = TextSection::default()
+            .with_layout(Layout::default().h_align(HorizontalAlign::Center))
+            .with_screen_position((
+                layout.hold_panel.x + layout.hold_panel.w / 2.0,
+                layout.hold_panel.y + layout.hold_panel.h - 120.0 * self.scale_factor,
+            ));
+        for (text, &held) in labels.iter().zip(&playback.held) {
+            let color = if held { yellow_color } else { dim_color };
+            input_section = input_section.add_text(
+                Text::new(text)
+                    .with_color(color)
+                    .with_scale(20.0 * self.scale_factor)
+                    .with_font_id(self.fonts[fonts::ARIAL_ROUNDED]),
+            );
+        }
+        sections.extend(self.make_owned_text_with_outline(input_section));
+
         sections
     }
 
@@ -198,13 +646,28 @@ impl Renderer {
         render_pass.set_pipeline(&self.tile_renderer.pipeline);
         render_pass.set_vertex_buffer(0, self.piece_vertex_buffer.slice(..));
 
-        let tile_width = 2.0 / game.board.width as f32;
-        let tile_height = 2.0 / game.board.height as f32;
+        let (width, height) = (canvas.config.width as f32, canvas.config.height as f32);
+        let layout = ScreenLayout::new(width, height, &game.board);
+        let board = layout.board.to_clip_space(width, height);
+        let hold = layout.hold.to_clip_space(width, height);
+        let preview_tile_width = hold.w / 6.0;
+        let preview_tile_height = preview_tile_width * width / height;
+
+        let tile_width = board.w / game.board.width as f32;
+        let tile_height = board.h / game.board.height as f32;
+
+        let background = Tile::new(board.w, board.h).at(board.x, board.y - board.h);
+        canvas.queue.write_buffer(
+            &self.piece_vertex_buffer,
+            0,
+            bytemuck::cast_slice(&background.vertices),
+        );
+        render_pass.set_bind_group(0, &self.board_background_bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
 
-        let mut vertices_written: u32 = 0;
-
-        for (&letter, bind_group) in &self.piece_texture_bind_groups {
-            let vertices = self.create_tile_vertices(game, tile_width, tile_height, letter);
+        let mut vertices_written: u32 = 6;
+        let mut draw_tiles = |tiles: Vec<Tile>, bind_group: &wgpu::BindGroup| {
+            let vertices = tiles.iter().flat_map(|t| t.vertices).collect::<Vec<_>>();
 
             let buffer_offset = vertices_written as u64 * Vertex::desc().array_stride;
 
@@ -220,30 +683,92 @@ impl Renderer {
             render_pass.draw(vertices_written..vertices_written + num_vertices, 0..1);
 
             vertices_written += num_vertices;
+        };
+
+        let ghost_tiles = self.create_ghost_tiles(game, &board, tile_width, tile_height);
+        draw_tiles(ghost_tiles, &self.ghost_bind_group);
+
+        let garbage_tiles =
+            self.create_board_tiles(game, &board, tile_width, tile_height, GARBAGE_TILE);
+        draw_tiles(garbage_tiles, &self.garbage_bind_group);
+
+        for (&letter, bind_group) in &self.piece_texture_bind_groups {
+            let mut tiles = self.create_board_tiles(game, &board, tile_width, tile_height, letter);
+
+            if game.state != GameState::Paused {
+                if let Some(held) = game.held_shape.filter(|&l| l == letter) {
+                    tiles.extend(preview_tiles(
+                        &game.shapes[&held],
+                        &hold,
+                        preview_tile_width,
+                        preview_tile_height,
+                    ));
+                }
+
+                let previews = game
+                    .next_shapes
+                    .iter()
+                    .take(game.rules.preview_count as usize);
+                for (i, &next) in previews.enumerate() {
+                    if next == letter {
+                        let slot = layout.next_queue_slot(i).to_clip_space(width, height);
+                        tiles.extend(preview_tiles(
+                            &game.shapes[&next],
+                            &slot,
+                            preview_tile_width,
+                            preview_tile_height,
+                        ));
+                    }
+                }
+            }
+
+            draw_tiles(tiles, bind_group);
         }
     }
 
-    fn create_tile_vertices(
+    fn create_board_tiles(
         &self,
         game: &Game,
+        board: &Rect,
         tile_width: f32,
         tile_height: f32,
         letter: char,
-    ) -> Vec<Vertex> {
-        let mut spots: Vec<(u8, u8)> = Vec::new();
+    ) -> Vec<Tile> {
+        let mut spots: Vec<(u8, u8, f32)> = Vec::new();
 
         if game.state != GameState::Paused {
-            for (y, row) in game.board.tiles.iter().enumerate() {
-                for (x, &l) in row.iter().enumerate() {
-                    if l == letter {
-                        spots.push((x as u8, y as u8));
+            for y in 0..game.board.height {
+                for x in 0..game.board.width {
+                    let pos = Pos::new(x as i8, y as i8);
+                    if game.board.get_tile(pos) == Some(letter) {
+                        spots.push((x, y, game.tile_opacity(pos)));
                     }
                 }
             }
         }
 
-        if letter == 'G'
-            && game.state != GameState::Paused
+        if let Some(piece) = game.moving_piece.filter(|p| p.letter == letter) {
+            for pos in piece.tiles(&game.shapes) {
+                if game.board.is_visible(pos) {
+                    spots.push((pos.x as u8, pos.y as u8, 1.0));
+                }
+            }
+        }
+
+        spots_to_tiles(&spots, board, tile_width, tile_height)
+    }
+
+    /// Shows where the moving piece would land
+    fn create_ghost_tiles(
+        &self,
+        game: &Game,
+        board: &Rect,
+        tile_width: f32,
+        tile_height: f32,
+    ) -> Vec<Tile> {
+        let mut spots: Vec<(u8, u8, f32)> = Vec::new();
+
+        if game.state != GameState::Paused
             && game.state != GameState::GameOver
             && let Some(piece) = game.moving_piece
         {
@@ -256,30 +781,20 @@ impl Renderer {
                 piece = updated;
             }
             for pos in piece.tiles(&game.shapes) {
-                if game.board.contains(pos) {
-                    spots.push((pos.x as u8, pos.y as u8));
+                if game.board.is_visible(pos) {
+                    spots.push((pos.x as u8, pos.y as u8, 1.0));
                 }
             }
         }
 
-        if let Some(piece) = game.moving_piece.filter(|p| p.letter == letter) {
-            for pos in piece.tiles(&game.shapes) {
-                if game.board.contains(pos) {
-                    spots.push((pos.x as u8, pos.y as u8));
-                }
-            }
-        }
+        spots_to_tiles(&spots, board, tile_width, tile_height)
+    }
 
-        let tiles = spots
+    fn make_owned_text_with_outline(&self, section: TextSection<'_>) -> Vec<OwnedSection> {
+        self.make_text_with_outline(section)
             .iter()
-            .map(|&(x, y)| {
-                let tx = tile_width * x as f32 - 1.0;
-                let ty = 1.0 - tile_height * (y + 1) as f32;
-                Tile::new(tile_width, tile_height).at(tx, ty)
-            })
-            .collect::<Vec<_>>();
-
-        tiles.iter().flat_map(|t| t.vertices).collect::<Vec<_>>()
+            .map(|s| s.to_owned())
+            .collect()
     }
 
     fn make_text_with_outline<'f>(&self, section: TextSection<'f>) -> Vec<TextSection<'f>> {
@@ -311,3 +826,145 @@ impl Renderer {
         res
     }
 }
+
+/// A rectangle with `(x, y)` at the top left corner
+struct Rect {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+}
+
+impl Rect {
+    /// Converts a rectangle in pixels to clip space, where `y` points up
+    fn to_clip_space(&self, screen_width: f32, screen_height: f32) -> Rect {
+        Rect {
+            x: self.x / screen_width * 2.0 - 1.0,
+            y: 1.0 - self.y / screen_height * 2.0,
+            w: self.w / screen_width * 2.0,
+            h: self.h / screen_height * 2.0,
+        }
+    }
+}
+
+/// Where the different parts of the game are placed on the screen (in pixels)
+struct ScreenLayout {
+    hold_panel: Rect,
+    /// The area the held piece is drawn in, inside the hold panel
+    hold: Rect,
+    board: Rect,
+    /// The column of upcoming pieces, to the right of the board
+    next_queue: Rect,
+}
+
+impl ScreenLayout {
+    /// The board is made as big as possible with square tiles, and the panels are placed next to it
+    fn new(screen_width: f32, screen_height: f32, board: &Board) -> Self {
+        let panel_width = screen_width / 4.0;
+        let tile_size = f32::min(
+            (screen_width - 2.0 * panel_width) / board.width as f32,
+            screen_height / board.height as f32,
+        );
+        let board_width = tile_size * board.width as f32;
+        let board_height = tile_size * board.height as f32;
+        let board_x = (screen_width - board_width) / 2.0;
+        let board_y = (screen_height - board_height) / 2.0;
+
+        Self {
+            hold_panel: Rect {
+                x: board_x - panel_width,
+                y: 0.0,
+                w: panel_width,
+                h: screen_height,
+            },
+            hold: Rect {
+                x: board_x - panel_width,
+                y: panel_width * 0.3,
+                w: panel_width,
+                h: panel_width * 0.6,
+            },
+            board: Rect {
+                x: board_x,
+                y: board_y,
+                w: board_width,
+                h: board_height,
+            },
+            next_queue: Rect {
+                x: board_x + board_width,
+                y: panel_width * 0.3,
+                w: panel_width,
+                h: screen_height - panel_width * 0.3,
+            },
+        }
+    }
+
+    /// The area of one of the upcoming pieces, with the next piece at index 0
+    fn next_queue_slot(&self, index: usize) -> Rect {
+        let slot_height = self.next_queue.w * 0.5;
+        Rect {
+            x: self.next_queue.x,
+            y: self.next_queue.y + slot_height * index as f32,
+            w: self.next_queue.w,
+            h: slot_height,
+        }
+    }
+}
+
+/// Tiles at board positions with an opacity each, where `board` is in clip space
+fn spots_to_tiles(
+    spots: &[(u8, u8, f32)],
+    board: &Rect,
+    tile_width: f32,
+    tile_height: f32,
+) -> Vec<Tile> {
+    spots
+        .iter()
+        .filter(|&&(_, _, opacity)| opacity > 0.0)
+        .map(|&(x, y, opacity)| {
+            let tx = board.x + tile_width * x as f32;
+            let ty = board.y - tile_height * (y + 1) as f32;
+            Tile::new(tile_width, tile_height)
+                .at(tx, ty)
+                .with_opacity(opacity)
+        })
+        .collect()
+}
+
+/// A bright color, going from red through the other colors of the rainbow as `t` goes from 0 to 1
+fn rainbow_color(t: f32) -> [u8; 3] {
+    let hue = t.fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u8 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    [r, g, b].map(|c: f32| (c * 220.0) as u8 + 35)
+}
+
+/// Tiles for a piece shown outside of the board, centered in `area` (in clip space)
+fn preview_tiles(shape: &Shape, area: &Rect, tile_width: f32, tile_height: f32) -> Vec<Tile> {
+    let cells = shape.at(Pos::new(0, 0));
+
+    let min_x = cells.iter().map(|p| p.x).min().unwrap_or(0);
+    let max_x = cells.iter().map(|p| p.x).max().unwrap_or(0);
+    let min_y = cells.iter().map(|p| p.y).min().unwrap_or(0);
+    let max_y = cells.iter().map(|p| p.y).max().unwrap_or(0);
+
+    let piece_width = (max_x - min_x + 1) as f32 * tile_width;
+    let piece_height = (max_y - min_y + 1) as f32 * tile_height;
+    let left = area.x + (area.w - piece_width) / 2.0;
+    let top = area.y - (area.h - piece_height) / 2.0;
+
+    cells
+        .iter()
+        .map(|p| {
+            let tx = left + tile_width * (p.x - min_x) as f32;
+            let ty = top - tile_height * (p.y - min_y + 1) as f32;
+            Tile::new(tile_width, tile_height).at(tx, ty)
+        })
+        .collect()
+}
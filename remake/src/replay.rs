@@ -0,0 +1,289 @@
+//! Replays of whole games, which can be played back to get the exact same game again.
+//!
+//! A replay file starts with the magic bytes `TRPL` and a format version, followed by the seed, the
+//! rules in the format of `snapshot.rs`, every update and input that reached the game, and a hash of
+//! the board if the game ended. Numbers are little endian, and lengths and events are LEB128
+//! encoded.
+
+use std::rc::Rc;
+
+use anyhow::{Context, bail, ensure};
+use chrono::TimeDelta;
+
+use crate::{
+    game::{Board, Game},
+    input::Action,
+    pieces::PieceSet,
+    rules::Rules,
+    snapshot::{SnapshotReader, SnapshotWriter, read_rules, write_rules},
+};
+
+const MAGIC: &[u8; 4] = b"TRPL";
+
+/// Changed whenever the format changes, so that old replays are not played back wrongly
+const VERSION: u16 = 2;
+
+/// Something that happened to the game, which has to happen again in the same order to replay it
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ReplayEvent {
+    /// Time passed while the game was running
+    Update(TimeDelta),
+    Press(Action),
+    Release(Action),
+    /// Every key was released at once, because the window lost focus
+    ReleaseAll,
+    /// The game was won and the player chose to keep playing
+    KeepPlaying,
+}
+
+impl ReplayEvent {
+    // The lowest two bits of an encoded event tell what kind it is
+    const UPDATE: u64 = 0;
+    const PRESS: u64 = 1;
+    const RELEASE: u64 = 2;
+    const OTHER: u64 = 3;
+
+    fn encode(self) -> u64 {
+        let action_index = |action| Action::ALL.iter().position(|&a| a == action).unwrap() as u64;
+        match self {
+            ReplayEvent::Update(time) => {
+                // Zigzag encoded, so that small negative times stay small
+                let nanos = time.num_nanoseconds().unwrap_or(i64::MAX);
+                let zigzag = ((nanos << 1) ^ (nanos >> 63)) as u64;
+                zigzag << 2 | Self::UPDATE
+            }
+            ReplayEvent::Press(action) => action_index(action) << 2 | Self::PRESS,
+            ReplayEvent::Release(action) => action_index(action) << 2 | Self::RELEASE,
+            ReplayEvent::ReleaseAll => Self::OTHER,
+            ReplayEvent::KeepPlaying => 1 << 2 | Self::OTHER,
+        }
+    }
+
+    fn decode(value: u64) -> anyhow::Result<ReplayEvent> {
+        let payload = value >> 2;
+        let action = || {
+            Action::ALL
+                .get(payload as usize)
+                .copied()
+                .with_context(|| format!("unknown action {payload}"))
+        };
+        Ok(match value & 3 {
+            Self::UPDATE => {
+                let nanos = (payload >> 1) as i64 ^ -((payload & 1) as i64);
+                ReplayEvent::Update(TimeDelta::nanoseconds(nanos))
+            }
+            Self::PRESS => ReplayEvent::Press(action()?),
+            Self::RELEASE => ReplayEvent::Release(action()?),
+            _ => match payload {
+                0 => ReplayEvent::ReleaseAll,
+                1 => ReplayEvent::KeepPlaying,
+                _ => bail!("unknown event {value}"),
+            },
+        })
+    }
+}
+
+/// Everything needed to play a game again
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    pub rules: Rules,
+    /// Checked when playing back, since the piece sets given on the command line can change
+    pub piece_set_name: String,
+    pub events: Vec<ReplayEvent>,
+    /// `Board::fingerprint` when the game ended, to check that playing back gives the same board
+    pub board_hash: Option<u32>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, rules: Rules, piece_set_name: String) -> Self {
+        Self {
+            seed,
+            rules,
+            piece_set_name,
+            events: Vec::new(),
+            board_hash: None,
+        }
+    }
+
+    pub fn record(&mut self, event: ReplayEvent) {
+        self.events.push(event);
+        self.board_hash = None;
+    }
+
+    /// Remembers how the board looked when the game ended
+    pub fn finish(&mut self, board: &Board) {
+        self.board_hash = Some(board.fingerprint());
+    }
+
+    /// A new game with the same seed and rules, which the events can be applied to
+    pub fn start_game(&self, piece_sets: Rc<[PieceSet]>) -> anyhow::Result<Game> {
+        let piece_set = piece_sets
+            .get(self.rules.piece_set)
+            .context("the piece set of the replay is not loaded")?;
+        ensure!(
+            piece_set.name == self.piece_set_name,
+            "the replay uses the piece set {}",
+            self.piece_set_name
+        );
+
+        let mut game = Game::new(self.rules, self.seed, piece_sets);
+        game.playback = true;
+        game.replay = None;
+        game.start();
+        Ok(game)
+    }
+
+    /// Plays the whole replay, giving the game as it was when the recording stopped
+    pub fn play(&self, piece_sets: Rc<[PieceSet]>) -> anyhow::Result<Game> {
+        let mut game = self.start_game(piece_sets)?;
+        for &event in &self.events {
+            game.apply_replay_event(event);
+        }
+        Ok(game)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut rules = SnapshotWriter::new();
+        write_rules(&mut rules, &self.rules);
+        rules.field("piece-set-name", &self.piece_set_name);
+        let rules = rules.finish();
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        write_varint(&mut bytes, rules.len() as u64);
+        bytes.extend_from_slice(rules.as_bytes());
+        write_varint(&mut bytes, self.events.len() as u64);
+        for event in &self.events {
+            write_varint(&mut bytes, event.encode());
+        }
+        match self.board_hash {
+            Some(hash) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&hash.to_le_bytes());
+            }
+            None => bytes.push(0),
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Replay> {
+        let mut bytes = bytes;
+        ensure!(take(&mut bytes, MAGIC.len())? == MAGIC, "not a replay file");
+        let version = u16::from_le_bytes(take(&mut bytes, 2)?.try_into()?);
+        ensure!(version == VERSION, "unsupported replay version {version}");
+        let seed = u64::from_le_bytes(take(&mut bytes, 8)?.try_into()?);
+
+        let rules_len = read_varint(&mut bytes)? as usize;
+        let rules_text = std::str::from_utf8(take(&mut bytes, rules_len)?)?;
+        let reader = SnapshotReader::new(rules_text).context("reading the rules")?;
+        let rules = read_rules(&reader).context("reading the rules")?;
+        let piece_set_name = reader.get("piece-set-name")?.to_string();
+
+        let event_count = read_varint(&mut bytes)?;
+        let events = (0..event_count)
+            .map(|_| ReplayEvent::decode(read_varint(&mut bytes)?))
+            .collect::<anyhow::Result<_>>()?;
+        let board_hash = match take(&mut bytes, 1)?[0] {
+            0 => None,
+            1 => Some(u32::from_le_bytes(take(&mut bytes, 4)?.try_into()?)),
+            flag => bail!("invalid board hash flag {flag}"),
+        };
+        ensure!(bytes.is_empty(), "unexpected data after the board hash");
+
+        Ok(Replay {
+            seed,
+            rules,
+            piece_set_name,
+            events,
+            board_hash,
+        })
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], count: usize) -> anyhow::Result<&'a [u8]> {
+    ensure!(bytes.len() >= count, "the replay ends too early");
+    let (taken, rest) = bytes.split_at(count);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = take(bytes, 1)?[0];
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("invalid number in the replay")
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::keyboard::KeyCode;
+
+    use super::*;
+    use crate::{game::GameState, mode::GameMode};
+
+    /// Plays a scripted game that is saved and continued halfway, and stops before it ends so that
+    /// nothing is written to storage
+    fn play_scripted(mode: GameMode, piece_sets: Rc<[PieceSet]>) -> Game {
+        let rules = Rules {
+            mode,
+            cheese_rows: 5,
+            ..Rules::default()
+        };
+        let mut game = Game::new(rules, 3, piece_sets.clone());
+        game.start();
+        let frame = TimeDelta::nanoseconds(16_666_667);
+        for i in 0..600 {
+            if i == 300 {
+                game = Game::from_snapshot(&game.to_snapshot(), piece_sets.clone()).unwrap();
+                game.handle_key(KeyCode::Enter, true);
+                assert_eq!(game.state, GameState::Running);
+            }
+            match i % 100 {
+                5 => game.handle_action(Action::MoveLeft, true),
+                25 => game.handle_action(Action::MoveLeft, false),
+                30 => game.handle_action(Action::RotateCcw, true),
+                // Puzzles end when their few pieces run out, so they are not dropped
+                40 if game.puzzle.is_none() => game.handle_action(Action::SoftDrop, true),
+                70 => game.handle_action(Action::SoftDrop, false),
+                80 if game.puzzle.is_none() => game.handle_action(Action::HardDrop, true),
+                _ => {}
+            }
+            game.update(frame);
+        }
+        game
+    }
+
+    #[test]
+    fn playing_back_gives_the_recorded_board() {
+        let piece_sets: Rc<[PieceSet]> = PieceSet::builtin().into();
+        for mode in GameMode::ALL {
+            let game = play_scripted(mode, piece_sets.clone());
+            assert!(game.in_progress(), "{} ended too early", mode.name());
+
+            let mut replay = game.replay.clone().unwrap();
+            replay.finish(&game.board);
+            let decoded = Replay::decode(&replay.encode()).unwrap();
+            assert_eq!(decoded, replay);
+
+            let played = decoded.play(piece_sets.clone()).unwrap();
+            assert_eq!(played.summary(), game.summary(), "{}", mode.name());
+            assert_eq!(decoded.board_hash, Some(played.board.fingerprint()));
+        }
+    }
+}
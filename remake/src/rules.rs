@@ -0,0 +1,185 @@
+use std::{ops::RangeInclusive, str::FromStr};
+
+use anyhow::Context;
+
+use crate::{
+    game::Pos, gravity::GravityCurve, mode::GameMode, randomizer::RandomizerKind,
+    scoring::ScoringTable,
+};
+
+pub const MAX_PREVIEW_COUNT: u8 = 7;
+
+pub const BOARD_WIDTHS: RangeInclusive<u8> = 4..=40;
+pub const BOARD_HEIGHTS: RangeInclusive<u8> = 4..=60;
+
+/// The choices for `Rules::cheese_rows` on the title screen
+pub const CHEESE_ROWS: [u8; 3] = [5, 10, 18];
+
+/// The choices for `Rules::cheese_messiness` on the title screen
+pub const CHEESE_MESSINESS: [u8; 4] = [0, 30, 70, 100];
+
+/// Settings that change how the game plays, chosen before the game starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rules {
+    pub mode: GameMode,
+    /// How long a game lasts, in modes with a time limit
+    pub time_limit_secs: u32,
+    /// How many garbage rows the board starts with, in modes that have them
+    pub cheese_rows: u8,
+    /// The chance in percent that the hole moves between two garbage rows
+    pub cheese_messiness: u8,
+    /// Whether locked tiles stay visible, fade out after `fade_delay_ms` or disappear at once.
+    /// The whole board is shown again when the game ends.
+    pub visibility: Visibility,
+    /// How long tiles stay visible after they lock, when they fade
+    pub fade_delay_ms: u32,
+    /// Number of columns, within `BOARD_WIDTHS`
+    pub board_width: u8,
+    /// Number of rows, within `BOARD_HEIGHTS`
+    pub board_height: u8,
+    pub scoring: ScoringTable,
+    pub randomizer: RandomizerKind,
+    /// Whether pieces can be turned 180° in a single move
+    pub rotation_180: bool,
+    /// How many upcoming pieces are shown (1 to `MAX_PREVIEW_COUNT`)
+    pub preview_count: u8,
+    /// How long a piece can rest on something before it locks
+    pub lock_delay_ms: u32,
+    /// How many times moving or rotating a resting piece can restart the lock delay
+    pub max_lock_resets: u32,
+    /// Delayed Auto Shift: how long left or right must be held before the piece starts repeating
+    pub das_ms: u32,
+    /// Auto Repeat Rate: the time between repeated moves, where 0 moves all the way at once
+    pub arr_ms: u32,
+    /// Either a multiple of the gravity or all the way down at once
+    pub soft_drop: SoftDrop,
+    pub top_out: TopOut,
+    pub gravity: GravityCurve,
+    /// Index into the piece sets the game was created with
+    pub piece_set: usize,
+    /// Index into the built-in puzzles, in puzzle mode
+    pub puzzle: usize,
+}
+
+/// How fast the piece falls while soft drop is held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftDrop {
+    /// This many times faster than gravity
+    Factor(u32),
+    /// All the way down at once, without locking
+    Instant,
+}
+
+/// Reads a factor like `20`, or `instant`
+impl FromStr for SoftDrop {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s == "instant" {
+            return Ok(SoftDrop::Instant);
+        }
+        let factor = s
+            .parse()
+            .with_context(|| format!("invalid soft drop: {s}"))?;
+        Ok(SoftDrop::Factor(factor))
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            mode: GameMode::default(),
+            time_limit_secs: 120,
+            cheese_rows: 10,
+            cheese_messiness: 30,
+            visibility: Visibility::default(),
+            fade_delay_ms: 3000,
+            board_width: 10,
+            board_height: 20,
+            scoring: ScoringTable::default(),
+            randomizer: RandomizerKind::default(),
+            rotation_180: false,
+            preview_count: 5,
+            lock_delay_ms: 500,
+            max_lock_resets: 15,
+            das_ms: 167,
+            arr_ms: 33,
+            soft_drop: SoftDrop::Factor(20),
+            top_out: TopOut::default(),
+            gravity: GravityCurve::default(),
+            piece_set: 0,
+            puzzle: 0,
+        }
+    }
+}
+
+/// When the game is lost because the stack got too high. A block out, where a new piece overlaps
+/// the stack when it spawns, always ends the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TopOut {
+    /// Only a block out ends the game
+    Block,
+    /// Also ends the game when a piece locks completely above the visible rows
+    #[default]
+    Lock,
+    /// Also ends the game when any part of a piece locks above the visible rows
+    PartialLock,
+}
+
+impl TopOut {
+    pub const ALL: [TopOut; 3] = [TopOut::Block, TopOut::Lock, TopOut::PartialLock];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TopOut::Block => "Block out",
+            TopOut::Lock => "Lock out",
+            TopOut::PartialLock => "Partial lock out",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&t| t == self).unwrap();
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+
+    /// Whether a piece locking at these positions ends the game
+    pub fn is_locked_out(self, tiles: &[Pos]) -> bool {
+        match self {
+            TopOut::Block => false,
+            TopOut::Lock => tiles.iter().all(|p| p.y < 0),
+            TopOut::PartialLock => tiles.iter().any(|p| p.y < 0),
+        }
+    }
+}
+
+/// Whether the tiles on the board can be seen once they lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Visibility {
+    #[default]
+    Visible,
+    /// Tiles fade out a while after they lock
+    Fading,
+    /// Tiles disappear as soon as they lock
+    Invisible,
+}
+
+impl Visibility {
+    pub const ALL: [Visibility; 3] = [
+        Visibility::Visible,
+        Visibility::Fading,
+        Visibility::Invisible,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Visibility::Visible => "Visible",
+            Visibility::Fading => "Fading",
+            Visibility::Invisible => "Invisible",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&v| v == self).unwrap();
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+}
@@ -0,0 +1,110 @@
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TSpin {
+    None,
+    Mini,
+    Full,
+}
+
+/// What happened when a piece locked
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LineClear {
+    pub rows: u8,
+    pub t_spin: TSpin,
+}
+
+impl LineClear {
+    /// The text to show for clears that are worth celebrating
+    pub fn name(self) -> Option<String> {
+        let rows = match self.rows {
+            0 => "",
+            1 => " Single",
+            2 => " Double",
+            3 => " Triple",
+            _ => " Quad",
+        };
+        match self.t_spin {
+            TSpin::None => None,
+            TSpin::Mini => Some(format!("T-Spin Mini{rows}")),
+            TSpin::Full => Some(format!("T-Spin{rows}")),
+        }
+    }
+}
+
+/// Decides how many points a cleared set of rows is worth
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ScoringTable {
+    /// The table from the original game: 10/25/50/85 points times the combo
+    #[default]
+    Martomate,
+    /// 100/300/500/800 points times the level, plus a combo bonus and drop points
+    Guideline,
+}
+
+impl ScoringTable {
+    pub const ALL: [ScoringTable; 2] = [ScoringTable::Martomate, ScoringTable::Guideline];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScoringTable::Martomate => "Martomate",
+            ScoringTable::Guideline => "Guideline",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&t| t == self).unwrap();
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+
+    /// `combo` is the number of clears in a row, including this one (so it is at least 1 if any rows
+    /// were cleared)
+    pub fn line_clear_points(self, clear: LineClear, combo: u32, level: u8) -> u32 {
+        let rows = clear.rows;
+        match self {
+            ScoringTable::Martomate => {
+                // The original game had no T-spins, so they simply count double
+                let points = match (rows, clear.t_spin) {
+                    (0, TSpin::Full) => 10,
+                    (0, _) => 0,
+                    (1, _) => 10,
+                    (2, _) => 25,
+                    (3, _) => 50,
+                    _ => 85,
+                };
+                let t_spin_multiplier = if clear.t_spin == TSpin::Full { 2 } else { 1 };
+                points * t_spin_multiplier * combo.max(1)
+            }
+            ScoringTable::Guideline => {
+                let points = match (clear.t_spin, rows) {
+                    (TSpin::None, 0) => 0,
+                    (TSpin::None, 1) => 100,
+                    (TSpin::None, 2) => 300,
+                    (TSpin::None, 3) => 500,
+                    (TSpin::None, _) => 800,
+                    (TSpin::Mini, 0) => 100,
+                    (TSpin::Mini, 1) => 200,
+                    (TSpin::Mini, _) => 400,
+                    (TSpin::Full, 0) => 400,
+                    (TSpin::Full, 1) => 800,
+                    (TSpin::Full, 2) => 1200,
+                    (TSpin::Full, _) => 1600,
+                };
+                let combo_bonus = 50 * combo.saturating_sub(1);
+                let multiplier = level as u32 + 1;
+                (points + combo_bonus) * multiplier
+            }
+        }
+    }
+
+    pub fn drop_points(self, rows: u32, hard_drop: bool) -> u32 {
+        match self {
+            ScoringTable::Martomate => 0,
+            ScoringTable::Guideline => {
+                if hard_drop {
+                    rows * 2
+                } else {
+                    rows
+                }
+            }
+        }
+    }
+}
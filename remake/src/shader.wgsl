@@ -1,11 +1,13 @@
 struct VertexInput {
     @location(0) position: vec2<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) opacity: f32,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) opacity: f32,
 };
 
 @vertex
@@ -15,6 +17,7 @@ fn vs_main(
     var out: VertexOutput;
     out.clip_position = vec4<f32>(model.position, 0.0, 1.0);
     out.tex_coords = model.tex_coords;
+    out.opacity = model.opacity;
     return out;
 }
 
@@ -25,5 +28,6 @@ var s_diffuse: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return vec4<f32>(color.rgb, color.a * in.opacity);
 }
@@ -0,0 +1,177 @@
+//! A text format for saving a game in progress, so that it can be continued later.
+//!
+//! Every line is a key and a value separated by a single space. Some keys, like the cells of the
+//! board, are repeated. The first line is the format version.
+
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{Context, ensure};
+use chrono::TimeDelta;
+
+use crate::{
+    gravity::GravityCurve,
+    mode::GameMode,
+    randomizer::RandomizerKind,
+    rules::{BOARD_HEIGHTS, BOARD_WIDTHS, MAX_PREVIEW_COUNT, Rules, SoftDrop, TopOut, Visibility},
+    scoring::ScoringTable,
+};
+
+/// Changed whenever the format changes, so that old saves are not loaded wrongly
+const VERSION: u32 = 2;
+
+/// The storage key of the saved game
+pub const STORAGE_KEY: &str = "saved-game";
+
+pub struct SnapshotWriter {
+    text: String,
+}
+
+impl SnapshotWriter {
+    pub fn new() -> Self {
+        let mut writer = Self {
+            text: String::new(),
+        };
+        writer.field("version", VERSION);
+        writer
+    }
+
+    pub fn field(&mut self, key: &str, value: impl Display) {
+        self.text.push_str(&format!("{key} {value}\n"));
+    }
+
+    /// Saved in nanoseconds, like the updates in replays, so that timers tick the same after loading
+    pub fn duration(&mut self, key: &str, value: TimeDelta) {
+        self.field(key, value.num_nanoseconds().unwrap_or(i64::MAX));
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+pub struct SnapshotReader<'a> {
+    fields: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(text: &'a str) -> anyhow::Result<Self> {
+        let fields: Vec<(&str, &str)> = text
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.split_once(' ').unwrap_or((line, "")))
+            .collect();
+
+        let reader = Self { fields };
+        let version: u32 = reader.parse("version")?;
+        ensure!(version == VERSION, "unsupported save version {version}");
+        Ok(reader)
+    }
+
+    pub fn get(&self, key: &str) -> anyhow::Result<&'a str> {
+        self.all(key)
+            .next()
+            .with_context(|| format!("missing {key}"))
+    }
+
+    /// Every value of a key that can be repeated, in order
+    pub fn all<'s>(&'s self, key: &'s str) -> impl Iterator<Item = &'a str> + 's {
+        self.fields
+            .iter()
+            .filter(move |&&(k, _)| k == key)
+            .map(|&(_, value)| value)
+    }
+
+    pub fn parse<T: FromStr>(&self, key: &str) -> anyhow::Result<T> {
+        self.get(key)?
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid {key}"))
+    }
+
+    /// A value that is only saved when there is one
+    pub fn optional<T: FromStr>(&self, key: &str) -> anyhow::Result<Option<T>> {
+        self.all(key)
+            .next()
+            .map(|value| value.parse().map_err(|_| anyhow::anyhow!("invalid {key}")))
+            .transpose()
+    }
+
+    pub fn duration(&self, key: &str) -> anyhow::Result<TimeDelta> {
+        Ok(TimeDelta::nanoseconds(self.parse(key)?))
+    }
+
+    /// A value that is saved using its name, like the rules on the title screen
+    pub fn named<T: Copy>(
+        &self,
+        key: &str,
+        all: &[T],
+        name: impl Fn(T) -> &'static str,
+    ) -> anyhow::Result<T> {
+        let value = self.get(key)?;
+        all.iter()
+            .copied()
+            .find(|&item| name(item) == value)
+            .with_context(|| format!("unknown {key}: {value}"))
+    }
+}
+
+pub fn write_rules(w: &mut SnapshotWriter, rules: &Rules) {
+    w.field("mode", rules.mode.name());
+    w.field("time-limit", rules.time_limit_secs);
+    w.field("cheese-rows", rules.cheese_rows);
+    w.field("cheese-messiness", rules.cheese_messiness);
+    w.field("visibility", rules.visibility.name());
+    w.field("fade-delay", rules.fade_delay_ms);
+    w.field("scoring", rules.scoring.name());
+    w.field("randomizer", rules.randomizer.name());
+    w.field("rotation-180", rules.rotation_180);
+    w.field("previews", rules.preview_count);
+    w.field("lock-delay", rules.lock_delay_ms);
+    w.field("max-lock-resets", rules.max_lock_resets);
+    w.field("das", rules.das_ms);
+    w.field("arr", rules.arr_ms);
+    match rules.soft_drop {
+        SoftDrop::Factor(factor) => w.field("soft-drop", factor),
+        SoftDrop::Instant => w.field("soft-drop", "instant"),
+    }
+    w.field("top-out", rules.top_out.name());
+    w.field("gravity", rules.gravity.name());
+    w.field("piece-set", rules.piece_set);
+    w.field("puzzle", rules.puzzle);
+    w.field("board-width", rules.board_width);
+    w.field("board-height", rules.board_height);
+}
+
+pub fn read_rules(r: &SnapshotReader) -> anyhow::Result<Rules> {
+    let rules = Rules {
+        mode: r.named("mode", &GameMode::ALL, GameMode::name)?,
+        time_limit_secs: r.parse("time-limit")?,
+        cheese_rows: r.parse("cheese-rows")?,
+        cheese_messiness: r.parse("cheese-messiness")?,
+        visibility: r.named("visibility", &Visibility::ALL, Visibility::name)?,
+        fade_delay_ms: r.parse("fade-delay")?,
+        scoring: r.named("scoring", &ScoringTable::ALL, ScoringTable::name)?,
+        randomizer: r.named("randomizer", &RandomizerKind::ALL, RandomizerKind::name)?,
+        rotation_180: r.parse("rotation-180")?,
+        preview_count: r.parse("previews")?,
+        lock_delay_ms: r.parse("lock-delay")?,
+        max_lock_resets: r.parse("max-lock-resets")?,
+        das_ms: r.parse("das")?,
+        arr_ms: r.parse("arr")?,
+        soft_drop: r.parse("soft-drop")?,
+        top_out: r.named("top-out", &TopOut::ALL, TopOut::name)?,
+        gravity: r.named("gravity", &GravityCurve::ALL, GravityCurve::name)?,
+        piece_set: r.parse("piece-set")?,
+        puzzle: r.parse("puzzle")?,
+        board_width: r.parse("board-width")?,
+        board_height: r.parse("board-height")?,
+    };
+    ensure!(
+        BOARD_WIDTHS.contains(&rules.board_width) && BOARD_HEIGHTS.contains(&rules.board_height),
+        "invalid board size"
+    );
+    ensure!(
+        (1..=MAX_PREVIEW_COUNT).contains(&rules.preview_count),
+        "invalid previews"
+    );
+    Ok(rules)
+}
@@ -0,0 +1,79 @@
+//! Wall kicks from the Super Rotation System (SRS).
+//!
+//! The tables are usually written with `y` pointing up, but here they have been
+//! flipped to match the board, where `y` points down.
+
+/// Which set of wall kicks a piece uses when it rotates
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KickTable {
+    /// Only the basic rotation is tried (used by the O piece)
+    None,
+    /// The table shared by the J, L, S, T and Z pieces
+    Jlstz,
+    /// The table used by the I piece
+    I,
+}
+
+const NO_KICKS: [(i8, i8); 1] = [(0, 0)];
+
+/// Indexed by the rotation state before a clockwise rotation
+const JLSTZ_CW: [[(i8, i8); 5]; 4] = [
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+];
+
+/// Indexed by the rotation state before a counter-clockwise rotation
+const JLSTZ_CCW: [[(i8, i8); 5]; 4] = [
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+];
+
+/// Indexed by the rotation state before a clockwise rotation
+const I_CW: [[(i8, i8); 5]; 4] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+    [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+    [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+];
+
+/// Indexed by the rotation state before a counter-clockwise rotation
+const I_CCW: [[(i8, i8); 5]; 4] = [
+    [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+    [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+    [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+    [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+];
+
+/// Indexed by the rotation state before a 180° rotation. SRS doesn't define any kicks for this,
+/// so these are taken from the common SRS+ extension. The I piece doesn't get any 180° kicks.
+const JLSTZ_180: [[(i8, i8); 6]; 4] = [
+    [(0, 0), (0, -1), (1, -1), (-1, -1), (1, 0), (-1, 0)],
+    [(0, 0), (1, 0), (1, -2), (1, -1), (0, -2), (0, -1)],
+    [(0, 0), (0, 1), (-1, 1), (1, 1), (-1, 0), (1, 0)],
+    [(0, 0), (-1, 0), (-1, -2), (-1, -1), (0, -2), (0, -1)],
+];
+
+impl KickTable {
+    /// The offsets to try, in order, when rotating from the `from` rotation state to the `to` state.
+    /// The first offset that doesn't collide should be used.
+    pub fn kicks(self, from: u8, to: u8) -> &'static [(i8, i8)] {
+        let from = from % 4;
+        let clockwise = (from + 1) % 4 == to % 4;
+        let counter_clockwise = (to + 1) % 4 == from;
+        let half_turn = (from + 2) % 4 == to % 4;
+
+        match self {
+            KickTable::None => &NO_KICKS,
+            KickTable::Jlstz if clockwise => &JLSTZ_CW[from as usize],
+            KickTable::Jlstz if counter_clockwise => &JLSTZ_CCW[from as usize],
+            KickTable::Jlstz if half_turn => &JLSTZ_180[from as usize],
+            KickTable::I if clockwise => &I_CW[from as usize],
+            KickTable::I if counter_clockwise => &I_CCW[from as usize],
+            _ => &NO_KICKS,
+        }
+    }
+}
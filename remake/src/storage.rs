@@ -0,0 +1,104 @@
+//! Small pieces of text that are kept between runs of the game, like best times.
+//! They are files in the user's data folder, or `localStorage` on the web.
+
+#[cfg(not(target_arch = "wasm32"))]
+fn path(key: &str) -> Option<std::path::PathBuf> {
+    use std::{env, path::PathBuf};
+
+    let data_dir = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+    Some(data_dir.join("martomate-tetris").join(key))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load(key: &str) -> Option<String> {
+    std::fs::read_to_string(path(key)?).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save(key: &str, value: &str) -> anyhow::Result<()> {
+    save_bytes(key, value.as_bytes())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_bytes(key: &str) -> Option<Vec<u8>> {
+    std::fs::read(path(key)?).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_bytes(key: &str, value: &[u8]) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    let path = path(key).context("no folder to save in")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("creating save folder")?;
+    }
+    std::fs::write(&path, value).with_context(|| format!("writing {}", path.display()))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn remove(key: &str) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    let path = path(key).context("no folder to save in")?;
+    match std::fs::remove_file(&path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("removing {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn load(key: &str) -> Option<String> {
+    local_storage()?.get_item(key).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn save(key: &str, value: &str) -> anyhow::Result<()> {
+    local_storage()
+        .ok_or_else(|| anyhow::anyhow!("localStorage is not available"))?
+        .set_item(key, value)
+        .map_err(|err| anyhow::anyhow!("writing to localStorage: {err:?}"))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn load_bytes(key: &str) -> Option<Vec<u8>> {
+    from_hex(&load(key)?).ok()
+}
+
+/// Only text can be kept in `localStorage`, so binary values are kept as hex
+#[cfg(target_arch = "wasm32")]
+pub fn save_bytes(key: &str, value: &[u8]) -> anyhow::Result<()> {
+    save(key, &to_hex(value))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn remove(key: &str) -> anyhow::Result<()> {
+    local_storage()
+        .ok_or_else(|| anyhow::anyhow!("localStorage is not available"))?
+        .remove_item(key)
+        .map_err(|err| anyhow::anyhow!("removing from localStorage: {err:?}"))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn from_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(
+        text.len().is_multiple_of(2),
+        "hex text must have an even length"
+    );
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| Ok(u8::from_str_radix(std::str::from_utf8(pair)?, 16)?))
+        .collect()
+}
@@ -5,11 +5,12 @@ use crate::texture;
 pub struct Vertex {
     position: [f32; 2],
     tex_coords: [f32; 2],
+    opacity: f32,
 }
 
 impl Vertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 2] =
-        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2];
+    const ATTRIBS: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32];
 
     pub const fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
@@ -34,27 +35,33 @@ impl Tile {
                 Vertex {
                     position: [0.0, h],
                     tex_coords: [0.0, 0.0],
+                    opacity: 1.0,
                 },
                 Vertex {
                     position: [0.0, 0.0],
                     tex_coords: [0.0, 1.0],
+                    opacity: 1.0,
                 },
                 Vertex {
                     position: [w, h],
                     tex_coords: [1.0, 0.0],
+                    opacity: 1.0,
                 },
                 // lower triangle
                 Vertex {
                     position: [w, h],
                     tex_coords: [1.0, 0.0],
+                    opacity: 1.0,
                 },
                 Vertex {
                     position: [0.0, 0.0],
                     tex_coords: [0.0, 1.0],
+                    opacity: 1.0,
                 },
                 Vertex {
                     position: [w, 0.0],
                     tex_coords: [1.0, 1.0],
+                    opacity: 1.0,
                 },
             ],
         }
@@ -67,6 +74,14 @@ impl Tile {
         }
         self
     }
+
+    /// From 0 (invisible) to 1 (the texture as it is)
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        for v in &mut self.vertices {
+            v.opacity = opacity;
+        }
+        self
+    }
 }
 
 pub struct TileRenderer {
@@ -124,7 +139,7 @@ impl TileRenderer {
                 entry_point: Some("fs_main"),
                 targets: &[Some(wgpu::ColorTargetState {
                     format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
@@ -42,4 +42,20 @@ impl Timer {
     pub fn reset(&mut self) {
         self.time = TimeDelta::zero();
     }
+
+    /// The time since the timer was started or last ticked
+    pub fn elapsed(&self) -> TimeDelta {
+        self.time
+    }
+
+    pub fn set_elapsed(&mut self, time: TimeDelta) {
+        self.time = time;
+    }
+}
+
+/// Formats a duration like `1:05.27`
+pub fn format_duration(duration: TimeDelta) -> String {
+    let centis = duration.num_milliseconds().max(0) / 10;
+    let (minutes, seconds, centis) = (centis / 6000, centis / 100 % 60, centis % 100);
+    format!("{minutes}:{seconds:02}.{centis:02}")
 }